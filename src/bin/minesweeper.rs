@@ -1,6 +1,6 @@
 use std::io::stdout;
 use crossterm::{
-    execute, queue, terminal, cursor, Result, 
+    execute, terminal, cursor, Result, 
     event::{self, Event, MouseEvent, MouseEventKind, KeyEvent, KeyCode},
 };
 
@@ -8,7 +8,7 @@ use crossterm::{
 fn start() -> Result<()> {
     execute!(stdout(), 
         terminal::EnterAlternateScreen, 
-        event::EnableMouseCapture, cursor::Hide);
+        event::EnableMouseCapture, cursor::Hide)?;
 
     loop {
         match event::read() {
@@ -21,7 +21,7 @@ fn start() -> Result<()> {
 
     execute!(stdout(), 
         terminal::LeaveAlternateScreen,    
-        event::DisableMouseCapture, cursor::Show);
+        event::DisableMouseCapture, cursor::Show)?;
 
     Ok(())
 }
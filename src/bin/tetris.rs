@@ -1,299 +1,150 @@
-use std::{env, fs, io::{stdout, Write}, time::{Instant, Duration}};
+use std::{env, fs, io::{self, stdout, Write}, path::{Path, PathBuf}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 use crossterm::{
 	execute,
-	queue, 
+	queue,
 	terminal::{self, ClearType},
-	cursor, 
-	event::{self, Event, KeyEvent, KeyCode},
+	cursor,
+	event::{self, Event, KeyEvent, KeyEventKind, KeyCode, KeyboardEnhancementFlags, PushKeyboardEnhancementFlags, PopKeyboardEnhancementFlags},
 	style::{Color, Stylize}
 };
-use rand::{self, seq::SliceRandom};
-use dirs;
+use tetris::{Block, BlockType, Board, Cell, Game, create_block, GameState, Input, LockMode, Mode, Playback, Puzzle, Randomizer, Replay, RotationSystem, Settings, Statistics, TICK, DEFAULT_WIDTH, DEFAULT_HEIGHT};
 
-const WIDTH : usize = 8;
-const HEIGHT : usize = 22;
-
-#[derive(Debug, Copy, Clone)]
-enum BlockType {
-	I, J, L, O, S, T, Z
-}
-
-
-struct Block {
-	tiles : [Vec<Vec<Option<Color>>>; 4],
-	rotation : usize,
-	x_pos : isize,
-	y_pos : isize,
-	name : BlockType
-}
+/// The letter keys already used by `handle_key`, which cannot be picked as the hold key.
+const BOUND_KEYS : [char; 3] = [' ', 'p', 'z'];
+const LABEL_DURATION : Duration = Duration::from_secs(2);
+const FLASH_INTERVAL : u128 = 250;
+/// How far the arrow keys seek in a replay, five seconds.
+const SEEK_TICKS : u64 = 300;
+const HIGHSCORE_FILE : &str = ".tetris-highscore";
+/// Stores how far each puzzle directory has been solved.
+const PUZZLE_PROGRESS_FILE : &str = ".tetris-puzzles";
+/// How long before a garbage row rises the warning is shown in survival mode.
+const GARBAGE_WARNING : Duration = Duration::from_secs(2);
 
 enum KeyAction {
-	None, Drop(bool), Move(bool), Pause, Exit 
+	None, Press(Input), Release(Input), Pause, Exit
 }
 
-fn color_map(tiles : [Vec<Vec<bool>>; 4], color : Color) -> [Vec<Vec<Option<Color>>>; 4] {
-	let it = tiles.into_iter().map(|shape| 
-		shape.into_iter().map(|row| row.into_iter().map(|b| 
-			if b {Some(color)} else {None}).collect::<Vec<Option<Color>>>()
-		).collect::<Vec<Vec<Option<Color>>>>()
-	);
-	let mut shapes : [Vec<Vec<Option<Color>>>; 4] = [vec![], vec![], vec![], vec![]];
-	for (index, value) in it.enumerate() {
-		shapes[index] = value;
-	}
-	return shapes;
+/// Short lived messages shown on top of the game.
+struct Messages {
+	pieces : usize,
+	label : Option<(String, Instant)>,
+	perfect_clear : Option<Instant>,
+	flash_visible : bool
 }
 
-fn create_block(name : BlockType) -> Block {
-	let tiles = match name {
-		BlockType::I => color_map([
-			vec![vec![false, false, false, false], vec![true, true, true, true],
-				vec![false, false, false, false], vec![false, false, false, false]],
-			vec![vec![false, false, true, false], vec![false, false, true, false],
-				vec![false, false, true, false], vec![false, false, true, false]],
-			vec![vec![false, false, false, false], vec![false, false, false, false],
-				vec![true, true, true, true], vec![false, false, false, false]],
-			vec![vec![false, true, false, false], vec![false, true, false, false],
-				vec![false, true, false, false], vec![false, true, false, false]]
-		], Color::Rgb{r : 0, g : 255, b: 255}),
-		BlockType::J => color_map([
-			vec![vec![true, false, false], vec![true, true, true], vec![false, false, false]],
-			vec![vec![false, true, true], vec![false, true, false], vec![false, true, false]],
-			vec![vec![false, false, false], vec![true, true, true], vec![false, false, true]],
-			vec![vec![false, true, false], vec![false, true, false], vec![true, true, false]]
-		], Color::Blue),
-		BlockType::L =>  color_map([
-			vec![vec![false, false, true], vec![true, true, true], vec![false, false, false]],
-			vec![vec![false, true, false], vec![false, true, false], vec![false, true, true]],
-			vec![vec![false, false, false], vec![true, true, true], vec![true, false, false]],
-			vec![vec![true, true, false], vec![false, true, false], vec![false, true, false]]
-		], Color::Rgb{r: 255, g: 127, b: 0}),
-		BlockType::O => color_map([
-			vec![vec![true, true], vec![true, true]],
-			vec![vec![true, true], vec![true, true]],
-			vec![vec![true, true], vec![true, true]],
-			vec![vec![true, true], vec![true, true]]
-		], Color::Yellow),
-		BlockType::S => color_map([
-			vec![vec![false, true, true], vec![true, true, false], vec![false, false, false]],
-			vec![vec![false, true, false], vec![false, true, true], vec![false, false, true]],
-			vec![vec![false, false, false], vec![false, true, true], vec![true, true, false]],
-			vec![vec![true, false, false], vec![true, true, false], vec![false, true, false]]
-		], Color::Green),
-		BlockType::T =>  color_map([
-			vec![vec![false, true, false], vec![true, true, true], vec![false, false, false]],
-			vec![vec![false, true, false], vec![false, true, true], vec![false, true, false]],
-			vec![vec![false, false, false], vec![true, true, true], vec![false, true, false]],
-			vec![vec![false, true, false], vec![true, true, false], vec![false, true, false]]
-		], Color::Rgb{r: 128, g: 0, b: 128}),
-		BlockType::Z => color_map([
-			vec![vec![true, true, false], vec![false, true, true], vec![false, false, false]],
-			vec![vec![false, false, true], vec![false, true, true], vec![false, true, false]],
-			vec![vec![false, false, false], vec![true, true, false], vec![false, true, true]],
-			vec![vec![false, true, false], vec![true, true, false], vec![true, false, false]]
-		], Color::Red)
-	};
-	let x = (WIDTH - tiles[0].len()) / 2;
-	Block {tiles, rotation : 0, x_pos : x as isize, y_pos : 0, name}
-}
+impl Messages {
+	fn new() -> Messages {
+		Messages {pieces : 0, label : None, perfect_clear : None, flash_visible : false}
+	}
 
-fn rotate(board : &[[Option<Color>; WIDTH]; HEIGHT], block : &mut Block, clockwise : bool) -> bool {
-	block.rotation = if clockwise {(block.rotation + 1) % 4} else {(block.rotation + 3) % 4};
-	if let BlockType::O = block.name {
-		return false;
-	}
-	let kicks = match block.name {
-		BlockType::I => match (clockwise, block.rotation) {
-			(true, 1) | (false, 2) => [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
-			(true, 2) | (false, 3) => [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
-			(true, 3) | (false, 0) => [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
-			(true, 0) | (false, 1) => [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
-			_ => unreachable!()
-		},
-		_ => match (clockwise, block.rotation) {
-			(true, 1) | (false, 1) => [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
-			(true, 2) | (false, 0)=> [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
-			(true, 3) | (false, 3)=> [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
-			(true, 0) | (false, 2) => [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
-			_ => unreachable!()
+	/// Picks up new messages from the game and expires old ones, returning `true` if they need to be redrawn.
+	fn update(&mut self, game : &Game) -> bool {
+		let mut changed = false;
+		if game.pieces() != self.pieces {
+			self.pieces = game.pieces();
+			if let Some(action) = game.last_action() {
+				self.label = Some((action.to_string(), Instant::now()));
+				if action.perfect_clear {
+					self.perfect_clear = Some(Instant::now());
+				}
+			}
 		}
-	};
-	let x = block.x_pos;
-	let y = block.y_pos;
-	for kick in kicks {
-		block.x_pos = x + kick.0;
-		block.y_pos = y + kick.1;
-		if !overlapps(board, block) {
-			return true;
+		if self.label.as_ref().is_some_and(|(_, shown)| shown.elapsed() >= LABEL_DURATION) {
+			self.label = None;
+			changed = true;
 		}
+		if self.perfect_clear.is_some_and(|shown| shown.elapsed() >= LABEL_DURATION) {
+			self.perfect_clear = None;
+		}
+		let visible = self.perfect_clear.is_some_and(|shown| (shown.elapsed().as_millis() / FLASH_INTERVAL).is_multiple_of(2));
+		if visible != self.flash_visible {
+			self.flash_visible = visible;
+			changed = true;
+		}
+		changed
 	}
-	
-	block.x_pos = x;
-	block.y_pos = y;
-	block.rotation = if clockwise {(block.rotation + 3) % 4} else {(block.rotation + 1) % 4};
-	return false;
-}
 
-fn move_block(board : &[[Option<Color>; WIDTH]; HEIGHT], block : &mut Block, dx : isize, dy : isize) -> bool {
-	block.x_pos += dx;
-	block.y_pos += dy;
-	if overlapps(board, block) {
-		block.x_pos -= dx;
-		block.y_pos -= dy;
-		return false;
+	fn label(&self) -> Option<&str> {
+		self.label.as_ref().map(|(text, _)| text.as_str())
 	}
-	return true;
 }
 
-fn in_bounds(block : &Block, x : usize, y : usize) -> bool {
-	let x = block.x_pos + x as isize;
-	let y = block.y_pos + y as isize;
-	return x >= 0 && y >= 0 && (x as usize) < WIDTH && (y as usize) < HEIGHT;
+struct Options {
+	use_color : bool,
+	show_ghost : bool,
+	inverse_rotation : bool,
+	hold_key : char,
+	preview : usize
 }
 
-fn overlapps(board : &[[Option<Color>; WIDTH]; HEIGHT], block : &Block) -> bool {
-	for y in 0..block.tiles[block.rotation].len() {
-		for x in 0..block.tiles[block.rotation][y].len() {
-			if block.tiles[block.rotation][y][x].is_some() {
-				if !in_bounds(block, x, y) || 
-					board[(y as isize + block.y_pos) as usize][(x as isize + block.x_pos) as usize].is_some() 
-				{
-					return true;
-				}
-			}
-		}
+fn block_color(kind : BlockType) -> Color {
+	match kind {
+		BlockType::I => Color::Rgb{r : 0, g : 255, b: 255},
+		BlockType::J => Color::Blue,
+		BlockType::L => Color::Rgb{r: 255, g: 127, b: 0},
+		BlockType::O => Color::Yellow,
+		BlockType::S => Color::Green,
+		BlockType::T => Color::Rgb{r: 128, g: 0, b: 128},
+		BlockType::Z => Color::Red
 	}
-	false
 }
 
-fn freeze(board : &mut [[Option<Color>; WIDTH]; HEIGHT], block : &Block) -> usize {
-	for y in 0..block.tiles[block.rotation].len() {
-		for x in 0..block.tiles[block.rotation][y].len() {
-			if !in_bounds(&block, x, y) {
-				continue;
-			}
-			let tile = block.tiles[block.rotation][y][x];
-			if tile.is_some() {
-				board[(y as isize + block.y_pos) as usize][(x as isize + block.x_pos) as usize] = tile;
-			}
-		}
+fn cell_color(cell : Cell) -> Color {
+	match cell {
+		Cell::Block(kind) => block_color(kind),
+		Cell::Garbage => Color::Grey
 	}
-	let mut cleared_rows = 0;
-	let mut index = board.len() - 1;
-	while {
-		if board[index].iter().all(|tile| tile.is_some()) {
-			for i in (1..(index + 1)).rev() {
-				board.copy_within((i - 1)..(i), i);
-			}
-			board[0].fill(None);
-			cleared_rows += 1;
-		} else {
-			index -= 1;
-		}
-		index > 0
-	}{}
-	cleared_rows
 }
 
-fn add_score(rows : usize, level : &mut usize, highscore: &mut usize, score : &mut usize, cleared_rows : &mut usize, delay : &mut Duration) {
-	if rows == 0 { 
-		return; 
-	}
-	
-	*score += *level * match rows {
-		1 => 100,
-		2 => 300,
-		3 => 500,
-		4 => 800,
-		_ => unreachable!("Not possible to clear more than 4 rows")
-	};
-	if score > highscore {
-		*highscore = *score;
-	}
-	*cleared_rows += rows;
-        loop {
-            let target_rows = match *level {
-                    1 => 10,
-                    2 => 30,
-                    3 => 70,
-                    4 => 120,
-                    5 => 180,
-                    6 => 250,
-                    7 => 330,
-                    8 => 420,
-                    9 => 520,
-                    10 => 620,
-                    11 => 720,
-                    12 => 820,
-                    13 => 920,
-                    14 => 1020,
-                    15 => 1120,
-                    16 => 1230,
-                    17 => 1350,
-                    18 => 1480,
-                    19 => 1620,
-                    20 => 1770,
-                    21 => 1930,
-                    22 => 2100,
-                    23 => 2280,
-                    24 => 2470,
-                    25 => 2670,
-                    26 => 2870,
-                    27 => 3070,
-                    28 => 3270,
-                    29 => usize::MAX,
-                    _ => unreachable!("Invalid level")
-            };
-            if *cleared_rows >= target_rows {
-                    *level += 1;
-                    let base : f64 = 1.142;
-                    let pw = 800.0 * base * base.powi(-(*level as i32));
-                    *delay = Duration::from_millis(pw as u64);
-            } else {
-                break;
-            }
-        }
+fn block_tile(block : Option<&Block>, x : usize, y : usize) -> Option<Color> {
+	let b = block?;
+	let tiles = b.tiles();
+	let (bx, by) = b.position();
+	let xi = x as isize - bx;
+	let yi = y as isize - by;
+	if yi >= 0 && yi < tiles.len() as isize && xi >= 0 && xi < tiles[0].len() as isize {
+		tiles[yi as usize][xi as usize].map(cell_color)
+	} else {
+		None
+	}
 }
 
-fn print_board(board : &[[Option<Color>; WIDTH]; HEIGHT], block : &Option<Block>, use_color : bool) -> crossterm::Result<()>{
+fn print_board(board : &Board, block : Option<&Block>, ghost : Option<&Block>, use_color : bool) -> crossterm::Result<()>{
 	queue!(stdout(), cursor::MoveTo(0, 0))?;
-    let mut edge = String::from("##");
-    for _ in 0..(WIDTH - 1) {
-        edge.push('#');
-        edge.push('#');
-    }
+	let mut edge = String::from("##");
+	for _ in 0..(board.width() - 1) {
+		edge.push('#');
+		edge.push('#');
+	}
 	edge.push('#');
 	edge.push('#');
 
 	print!("{}", edge);
 	queue!(stdout(), cursor::MoveToNextLine(1))?;
-	for (y, row) in board.iter().enumerate() {
+	let hidden_rows = board.hidden_rows();
+	for (y, row) in board.visible_rows().iter().enumerate() {
+		let y = y + hidden_rows;
 		print!("#");
 		for (x, c) in row.iter().enumerate() {
-			if let Some(b) = block {
-				let xi = x as isize;
-				let yi = y as isize;
-				let tiles = &b.tiles[b.rotation];
-				if 
-					yi >= b.y_pos && yi < b.y_pos + tiles.len() as isize &&
-					xi >= b.x_pos && xi < b.x_pos + tiles[0].len() as isize
-				{
-					if let Some(color) = tiles[(yi - b.y_pos) as usize][(xi - b.x_pos) as usize] {
-						if use_color {
-							print!("{}", "██".with(color).on(color));
-                                                } else {
-							print!("▓▓");
-						}
-						continue;
-					}
+			if let Some(color) = block_tile(block, x, y) {
+				if use_color {
+					print!("{}", "██".with(color).on(color));
+				} else {
+					print!("▓▓");
 				}
-			}
-			if let Some(color) = *c {
+			} else if let Some(color) = c.map(cell_color) {
 				if use_color {
 					print!("{}", "██".with(color).on(color));
 				} else {
 					print!("██");
 				}
+			} else if let Some(color) = block_tile(ghost, x, y) {
+				if use_color {
+					print!("{}", "░░".with(color));
+				} else {
+					print!("░░");
+				}
 			} else {
 				print!("  ");
 			}
@@ -301,75 +152,228 @@ fn print_board(board : &[[Option<Color>; WIDTH]; HEIGHT], block : &Option<Block>
 		print!("#");
 		queue!(stdout(), cursor::MoveToNextLine(1))?;
 	}
-	edge = String::from("##");
-    for _ in 0..(WIDTH - 1) {
-        edge.push('#');
-        edge.push('#');
-    }
-	edge.push('#');
-	edge.push('#');
 	print!("{}", edge);
 	execute!(stdout(), cursor::MoveToNextLine(1))?;
 	Ok(())
 }
 
-fn print_ui(block : &Block, highscore: usize, score : usize, rows : usize, level : usize, delay : Duration, use_color : bool) -> crossterm::Result<()> {
-    queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 4, 0))?;
-	print!("NEXT:");
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 2, 2))?;
-	for _ in 0..5{
-		print!("                 ");
-		queue!(stdout(), cursor::MoveToColumn(2 * WIDTH as u16 + 2), cursor::MoveDown(1))?;
+fn print_piece(block : Option<&Block>, column : u16, row : u16, dim : bool, use_color : bool) -> crossterm::Result<()> {
+	queue!(stdout(), cursor::MoveTo(column, row))?;
+	for _ in 0..2 {
+		print!("            ");
+		queue!(stdout(), cursor::MoveToColumn(column), cursor::MoveDown(1))?;
 	}
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 2, 2))?;
-	for row in &block.tiles[block.rotation] {
+	queue!(stdout(), cursor::MoveTo(column, row))?;
+	let tiles = block.map(|b| b.tiles()).unwrap_or_default();
+	for row in tiles.iter().filter(|row| row.iter().any(|tile| tile.is_some())) {
 		print!("  ");
 		for col in row {
-			if let Some(color) = col {
+			if let Some(cell) = col {
+				let color = if dim {Color::DarkGrey} else {cell_color(*cell)};
 				if use_color {
-					print!("{}", "██".with(*color).on(*color));
+					print!("{}", "██".with(color).on(color));
+				} else if dim {
+					print!("░░");
 				} else {
 					print!("▓▓");
 				}
 			} else {
 				print!("  ");
 			}
-			
 		}
-		queue!(stdout(), cursor::MoveToColumn(2 * WIDTH as u16 + 2), cursor::MoveDown(1))?;
-	}
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 6))?;
-	print!("Highscore: {}", highscore);
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 8))?;
-	print!("Score: {}", score);
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 9))?;
-	print!("Level: {}", level);
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 10))?;
-	print!("Lines: {}", rows);
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 11))?;
-	print!("Delay: {:?}", delay);
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 7, 13))?;
+		queue!(stdout(), cursor::MoveToColumn(column), cursor::MoveDown(1))?;
+	}
+	Ok(())
+}
+
+fn print_ui(game : &Game, record : &str, label : Option<&str>, controls : &[String], options : &Options) -> crossterm::Result<()> {
+	let use_color = options.use_color;
+	let width = game.board().width() as u16;
+	queue!(stdout(), cursor::MoveTo(2 * width + 4, 0))?;
+	print!("NEXT:");
+	let next = game.next_queue().take(options.preview).map(|kind| create_block(kind, game.settings().rotation));
+	for (i, block) in next.enumerate() {
+		print_piece(Some(&block), 2 * width + 2, 2 + 3 * i as u16, false, use_color)?;
+	}
+	let panel = 2 * width + 14;
+	if game.settings().hold {
+		queue!(stdout(), cursor::MoveTo(panel + 2, 0))?;
+		print!("HOLD:");
+		print_piece(game.held_piece(), panel, 2, !game.can_hold(), use_color)?;
+	}
+	// Labels differ in length, so clear whatever the last one left behind.
+	queue!(stdout(), cursor::MoveTo(panel + 1, 4), terminal::Clear(ClearType::UntilNewLine))?;
+	print!("{}", label.unwrap_or(""));
+	queue!(stdout(), cursor::MoveTo(panel + 1, 5))?;
+	print!("{}", record);
+	queue!(stdout(), cursor::MoveTo(panel + 1, 6))?;
+	print!("Score: {}", game.score());
+	queue!(stdout(), cursor::MoveTo(panel + 1, 7))?;
+	match game.settings().mode {
+		// The level stops at the end of each section until a line is cleared.
+		Mode::Master {..} => print!("Level: {}/{}", game.level(), (game.level() / 100 * 100 + 99).min(999)),
+		_ => print!("Level: {}", game.level())
+	}
+	queue!(stdout(), cursor::MoveTo(panel + 1, 8))?;
+	match game.settings().mode {
+		Mode::Sprint {lines} | Mode::Marathon {lines} => print!("Lines: {}/{}", game.lines(), lines),
+		Mode::Dig {rows, ..} => print!("Garbage: {}/{}  ", game.board().garbage_rows(), rows),
+		_ => print!("Lines: {}", game.lines())
+	}
+	queue!(stdout(), cursor::MoveTo(panel + 1, 9))?;
+	match game.settings().mode {
+		Mode::Master {..} => print!("Gravity: {:.2}G  ", game.gravity()),
+		// The time until the next garbage row is shown here instead.
+		Mode::Survival {..} => (),
+		_ => print!("Delay: {:?}", game.delay())
+	}
+	queue!(stdout(), cursor::MoveTo(panel + 1, 10))?;
+	match game.combo() {
+		Some(combo) if combo > 0 => print!("Combo: {:<6}", combo),
+		_ => print!("Combo: -     ")
+	}
+	queue!(stdout(), cursor::MoveTo(panel + 1, 11))?;
+	print!("B2B: {:<4}", if game.back_to_back() {"Yes"} else {"No"});
+	print_time(game, use_color)?;
+	queue!(stdout(), cursor::MoveTo(panel + 5, 13))?;
 	print!("Controls:");
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 15))?;
-	print!("Rotate clockwise : Up arrow");
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 16))?;
-	print!("Rotate counter-clockwise : Z");
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 17))?;
-	print!("Soft drop : Down arrow");
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 18))?;
-	print!("Hard drop : Space");
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 19))?;
-	print!("Move left : Left arrow");
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 20))?;
-	print!("Move right : Right arrow");
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 21))?;
-	print!("Pause : P");
-	queue!(stdout(), cursor::MoveTo(2 * WIDTH as u16 + 3, 22))?;
-	print!("Exit : Esc");
+	for (i, line) in controls.iter().enumerate() {
+		queue!(stdout(), cursor::MoveTo(panel + 1, 14 + i as u16))?;
+		print!("{}", line);
+	}
 	stdout().flush()?;
 	Ok(())
 }
 
+/// Updates only the timers, which change every tick. In survival mode the bottom of the left
+/// wall turns into a warning shortly before the next garbage row rises.
+fn print_time(game : &Game, use_color : bool) -> crossterm::Result<()> {
+	let panel = 2 * game.board().width() as u16 + 14;
+	queue!(stdout(), cursor::MoveTo(panel + 1, 12))?;
+	match game.time_left() {
+		Some(time_left) => print!("Time left: {}", format_time(time_left)),
+		None => print!("Time: {}", format_time(game.time()))
+	}
+	if let Some(next_garbage) = game.next_garbage() {
+		queue!(stdout(), cursor::MoveTo(panel + 1, 9))?;
+		print!("Garbage in: {:.1}s  ", next_garbage.as_secs_f64());
+		let warning = next_garbage <= GARBAGE_WARNING;
+		queue!(stdout(), cursor::MoveTo(0, game.board().visible_rows().len() as u16))?;
+		match (warning, use_color) {
+			(true, true) => print!("{}", "▲".with(Color::Red)),
+			(true, false) => print!("▲"),
+			(false, _) => print!("#")
+		}
+	}
+	stdout().flush()
+}
+
+fn game_controls(options : &Options, hold : bool) -> Vec<String> {
+	let mut controls = vec![
+		String::from("Rotate clockwise : Up arrow"),
+		String::from("Rotate counter-clockwise : Z"),
+		String::from("Soft drop : Down arrow"),
+		String::from("Hard drop : Space"),
+		String::from("Move left : Left arrow"),
+		String::from("Move right : Right arrow")
+	];
+	if hold {
+		controls.push(format!("Hold : {}", options.hold_key.to_ascii_uppercase()));
+	}
+	controls.push(String::from("Pause : P"));
+	controls.push(String::from("Exit : Esc"));
+	controls
+}
+
+fn print_game(game : &Game, record : &str, messages : &Messages, controls : &[String], options : &Options) -> crossterm::Result<()> {
+	let ghost = if options.show_ghost {game.ghost_piece()} else {None};
+	print_board(game.board(), game.active_piece(), ghost.as_ref(), options.use_color)?;
+	if messages.flash_visible {
+		let text = "PERFECT CLEAR";
+		let board = game.board();
+		queue!(stdout(), cursor::MoveTo((2 * board.width() as u16 + 2).saturating_sub(text.len() as u16) / 2, board.visible_rows().len() as u16 / 2))?;
+		if options.use_color {
+			print!("{}", text.with(Color::Yellow).bold());
+		} else {
+			print!("{}", text);
+		}
+	}
+	print_ui(game, record, messages.label(), controls, options)
+}
+
+fn format_time(time : Duration) -> String {
+	let seconds = time.as_secs();
+	format!("{}:{:02}.{:03}", seconds / 60, seconds % 60, time.subsec_millis())
+}
+
+fn play_replay(replay : Replay, options : &Options) -> crossterm::Result<()> {
+	let length = TICK * replay.length() as u32;
+	let mut playback = Playback::new(replay);
+	let controls = vec![
+		String::from("Pause : Space"),
+		String::from("Step : S"),
+		String::from("Faster : Up arrow"),
+		String::from("Slower : Down arrow"),
+		String::from("Back 5s : Left arrow"),
+		String::from("Forward 5s : Right arrow"),
+		String::from("Exit : Esc")
+	];
+	let mut messages = Messages::new();
+	let mut paused = false;
+	// Playback runs at 2^speed times the normal rate.
+	let mut speed : i32 = 0;
+	let mut next_tick = Instant::now() + TICK;
+	let mut changed = true;
+	loop {
+		let interval = TICK.mul_f64(0.5f64.powi(speed));
+		let now = Instant::now();
+		let running = !paused && !playback.is_finished();
+		if running && now >= next_tick {
+			next_tick += interval;
+			changed |= playback.advance();
+		} else if event::poll(if running {next_tick - now} else {TICK})? {
+			let Event::Key(KeyEvent {code, kind : KeyEventKind::Press | KeyEventKind::Repeat, ..}) = event::read()? else {
+				continue;
+			};
+			let ticks = playback.game().ticks();
+			match code {
+				KeyCode::Esc => break,
+				KeyCode::Char(' ') | KeyCode::Char('P') | KeyCode::Char('p') => paused = !paused,
+				KeyCode::Char('S') | KeyCode::Char('s') => {
+					paused = true;
+					playback.advance();
+				},
+				KeyCode::Up => speed = (speed + 1).min(3),
+				KeyCode::Down => speed = (speed - 1).max(-2),
+				KeyCode::Left => playback.seek(ticks.saturating_sub(SEEK_TICKS)),
+				KeyCode::Right => playback.seek(ticks + SEEK_TICKS),
+				_ => continue
+			}
+			// Nothing ran while paused or at the end, so do not catch up on those ticks.
+			next_tick = Instant::now() + interval;
+			changed = true;
+		}
+		changed |= messages.update(playback.game());
+		if !changed {
+			continue;
+		}
+		changed = false;
+		let game = playback.game();
+		print_game(game, "Replay", &messages, &controls, options)?;
+		let status = match game.state() {
+			GameState::GameOver(loss) => loss.to_string(),
+			GameState::Finished => String::from("Finished"),
+			GameState::Running if playback.is_finished() => String::from("End"),
+			GameState::Running if paused => String::from("Paused"),
+			GameState::Running => String::new()
+		};
+		queue!(stdout(), cursor::MoveTo(0, game.board().visible_rows().len() as u16 + 2))?;
+		print!("Replay {} / {} {}x {:<12}", format_time(TICK * game.ticks() as u32), format_time(length), 2f64.powi(speed), status);
+		stdout().flush()?;
+	}
+	Ok(())
+}
+
 fn pause() -> crossterm::Result<()> {
 	queue!(stdout(), cursor::MoveTo(2, 10), terminal::Clear(ClearType::All))?;
 	print!("Game is Paused");
@@ -377,11 +381,11 @@ fn pause() -> crossterm::Result<()> {
 	loop {
 		match event::read() {
 			Ok(Event::Key(KeyEvent {
-				code : KeyCode::Char('P'), .. 
+				code : KeyCode::Char('P'), kind : KeyEventKind::Press, ..
 			})) | Ok(Event::Key(KeyEvent {
-				code : KeyCode::Char('p'), .. 
+				code : KeyCode::Char('p'), kind : KeyEventKind::Press, ..
 			})) | Ok(Event::Key(KeyEvent {
-				code : KeyCode::Esc, ..
+				code : KeyCode::Esc, kind : KeyEventKind::Press, ..
 			})) => break,
 			_ => ()
 		}
@@ -389,28 +393,59 @@ fn pause() -> crossterm::Result<()> {
 	Ok(())
 }
 
-fn print_game_over(score : usize, level : usize) -> crossterm::Result<bool> {
-	queue!(stdout(), terminal::Clear(ClearType::All), cursor::MoveTo(10, 3))?;
-	print!("Game Over");
-	queue!(stdout(), cursor::MoveTo(10, 5))?;
-	print!("Score : {}", score);
-	queue!(stdout(), cursor::MoveTo(10, 7))?;
-	print!("Level : {}", level);
-	queue!(stdout(), cursor::MoveTo(6, 9))?;
-	print!("Press R to play again");
-	queue!(stdout(), cursor::MoveTo(7, 11))?;
+/// Shows the end of a game, with `result` describing how it went compared to the personal best
+/// and `again` saying what pressing R does.
+fn print_game_over(game : &Game, result : &[String], again : &str, session : &Statistics) -> crossterm::Result<bool> {
+	let statistics = game.statistics();
+	let mut lines = vec![
+		match game.state() {
+			GameState::GameOver(loss) => format!("Game Over : {}", loss),
+			_ if game.goal().is_some() => String::from("Puzzle solved!"),
+			_ => match game.settings().mode {
+				Mode::Marathon {lines} => format!("Victory! All {} lines cleared", lines),
+				Mode::Sprint {lines} => format!("Sprint of {} lines complete!", lines),
+				Mode::Ultra {..} => String::from("Time is up!"),
+				Mode::Dig {rows, ..} => format!("All {} garbage rows cleared!", rows),
+				Mode::Master {..} => String::from("Level 999 reached!"),
+				Mode::Endless | Mode::Zen {..} | Mode::Survival {..} => String::from("Finished!")
+			}
+		},
+		String::new(),
+		format!("Score : {}", game.score()),
+		format!("Level : {}", game.level())
+	];
+	lines.extend(result.iter().cloned());
+	lines.extend([
+		String::new(),
+		format!("Tetrises : {} ({} this session)", statistics.tetrises, session.tetrises),
+		format!("T-spins : {} ({} this session)", statistics.t_spins, session.t_spins),
+		format!("Perfect clears : {} ({} this session)", statistics.perfect_clears, session.perfect_clears),
+		format!("Max combo : {} ({} this session)", statistics.max_combo, session.max_combo)
+	]);
+	if game.goal().is_none() {
+		lines.extend([String::new(), format!("Seed : {}", game.seed())]);
+	}
+	queue!(stdout(), terminal::Clear(ClearType::All))?;
+	for (i, line) in lines.iter().enumerate() {
+		queue!(stdout(), cursor::MoveTo(10, 3 + i as u16))?;
+		print!("{}", line);
+	}
+	let row = 3 + lines.len() as u16;
+	queue!(stdout(), cursor::MoveTo(6, row + 1))?;
+	print!("Press R {}", again);
+	queue!(stdout(), cursor::MoveTo(7, row + 3))?;
 	print!("Press esc to exit");
 	stdout().flush()?;
 	let res = loop {
 		match event::read() {
 			Ok(Event::Key(KeyEvent {
-				code : KeyCode::Esc, ..
+				code : KeyCode::Esc, kind : KeyEventKind::Press, ..
 			})) => break Ok(false),
 			Ok(Event::Key(KeyEvent {
-				code : KeyCode::Char('R'), ..
+				code : KeyCode::Char('R'), kind : KeyEventKind::Press, ..
 			})) |
 			Ok(Event::Key(KeyEvent {
-				code : KeyCode::Char('r'), ..
+				code : KeyCode::Char('r'), kind : KeyEventKind::Press, ..
 			})) => break Ok(true),
 			_ => ()
 		}
@@ -419,205 +454,466 @@ fn print_game_over(score : usize, level : usize) -> crossterm::Result<bool> {
 	res
 }
 
-fn handle_key(board : &[[Option<Color>; WIDTH]; HEIGHT], block : &mut Option<Block>, rotation_dir : bool) -> KeyAction {
-	if let Ok(true) = event::poll(Duration::from_millis(1)) {
-		let event = event::read();
-		match event {
-			Ok(Event::Key(KeyEvent {
-				code : keycode, ..
-			})) => match (keycode, block.as_mut()) {
-					(KeyCode::Esc, _) => KeyAction::Exit,
-					(KeyCode::Up, Some(block)) => KeyAction::Move(rotate(&board, block, rotation_dir)),
-					(KeyCode::Char('Z'), Some(block)) |
-					(KeyCode::Char('z'), Some(block)) => KeyAction::Move(rotate(&board, block, !rotation_dir)),
-					(KeyCode::Down, _) => KeyAction::Drop(false),
-					(KeyCode::Left, Some(block)) => KeyAction::Move(move_block(&board, block, -1, 0)),
-					(KeyCode::Right, Some(block)) => KeyAction::Move(move_block(&board, block, 1, 0)),
-					(KeyCode::Char(' '), Some(block)) => {
-						while move_block(board, block, 0, 1) {}
-						KeyAction::Drop(true)
-					},
-					(KeyCode::Char('P'), _) |
-					(KeyCode::Char('p'), _) => KeyAction::Pause,
-					_ => KeyAction::None
-			},
-			_ => KeyAction::None
-		}
-	} else {
-		KeyAction::None
+/// Maps a key event to an action. Without release events, held keys are only seen through
+/// the terminal's own key repeat, so repeats count as presses.
+fn handle_key(event : Event, options : &Options, releases : bool) -> KeyAction {
+	let rotation_dir = !options.inverse_rotation;
+	let Event::Key(KeyEvent {code : keycode, kind, ..}) = event else {
+		return KeyAction::None;
+	};
+	let action = match keycode {
+		KeyCode::Esc => KeyAction::Exit,
+		KeyCode::Up if rotation_dir => KeyAction::Press(Input::RotateClockwise),
+		KeyCode::Up => KeyAction::Press(Input::RotateCounterClockwise),
+		KeyCode::Char('Z') | KeyCode::Char('z') if rotation_dir => KeyAction::Press(Input::RotateCounterClockwise),
+		KeyCode::Char('Z') | KeyCode::Char('z') => KeyAction::Press(Input::RotateClockwise),
+		KeyCode::Down => KeyAction::Press(Input::SoftDrop),
+		KeyCode::Left => KeyAction::Press(Input::MoveLeft),
+		KeyCode::Right => KeyAction::Press(Input::MoveRight),
+		KeyCode::Char(' ') => KeyAction::Press(Input::HardDrop),
+		KeyCode::Char(c) if c.eq_ignore_ascii_case(&options.hold_key) => KeyAction::Press(Input::Hold),
+		KeyCode::Char('P') |
+		KeyCode::Char('p') => KeyAction::Pause,
+		_ => KeyAction::None
+	};
+	match (action, kind) {
+		(KeyAction::Press(input), KeyEventKind::Release) => KeyAction::Release(input),
+		(_, KeyEventKind::Release) => KeyAction::None,
+		(_, KeyEventKind::Repeat) if releases => KeyAction::None,
+		(action, _) => action
 	}
 }
 
-fn read_highscore() -> usize {
+fn arg_value(name : &str) -> Option<String> {
+	env::args().skip_while(|s| s != name).nth(1)
+}
+
+/// The directory replays are written to, `--record <directory>` or `~/.tetris-replays`.
+fn replay_dir() -> Option<PathBuf> {
+	arg_value("--record").map(PathBuf::from).or_else(|| dirs::home_dir().map(|home| home.join(".tetris-replays")))
+}
+
+/// Writes the replay of a game to its own file, named after the time it was saved and the
+/// seed so that no game overwrites another. Returns the path of the file.
+fn save_replay(game : &Game, dir : &Path) -> Option<PathBuf> {
+	let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+	let path = dir.join(format!("{}-{}.replay", time.as_millis(), game.seed()));
+	fs::create_dir_all(dir).ok()?;
+	game.replay().save(&path).ok()?;
+	Some(path)
+}
+
+/// Reads the personal bests stored in a file in the home directory.
+fn read_highscore(file_name : &str) -> Vec<usize> {
 	if let Some(mut score_path) = dirs::home_dir() {
-		score_path.push(".tetris-highscore");
+		score_path.push(file_name);
 		if let Ok(score_bytes) = fs::read(score_path) {
-			if score_bytes.len() != (usize::BITS / 8) as usize {
-				return 0;
+			let size = (usize::BITS / 8) as usize;
+			if score_bytes.is_empty() || score_bytes.len() % size != 0 {
+				return vec![];
 			}
-			return score_bytes.into_iter().enumerate().fold(0, |score, (i, b)| (score << 8) + b.wrapping_add(5 * i as u8) as usize);
+			return score_bytes.chunks(size).map(|bytes|
+				bytes.iter().enumerate().fold(0, |score, (i, &b)| (score << 8) + b.wrapping_add(5 * i as u8) as usize)
+			).collect();
 		}
 	}
-	return 0;
+	vec![]
 }
 
-fn write_highscore(highscore: usize) {
+fn write_highscore(file_name : &str, highscores : &[usize]) {
 	if let Some(mut score_path) = dirs::home_dir() {
-		score_path.push(".tetris-highscore");
-		let out : Vec<u8> = highscore.to_be_bytes().iter().enumerate().map(|(i, b)| b.wrapping_sub(i as u8 * 5)).collect();
+		score_path.push(file_name);
+		let out : Vec<u8> = highscores.iter().flat_map(|highscore|
+			highscore.to_be_bytes().into_iter().enumerate().map(|(i, b)| b.wrapping_sub(i as u8 * 5))
+		).collect();
 		let _ = fs::write(score_path, out);
 	}
 }
- 
+
+/// Whether a mode is ranked by score, by the shortest time or by the longest time, in milliseconds.
+#[derive(PartialEq, Eq)]
+enum Ranking {
+	Score, Time, Survival
+}
+
+/// The personal bests of one mode, best first.
+struct Records {
+	file_name : String,
+	ranking : Ranking,
+	size : usize,
+	entries : Vec<usize>,
+	update : bool
+}
+
+impl Records {
+	/// Loads the records of `mode`. Modes with settings that change the difficulty keep
+	/// separate records for each of them.
+	fn new(mode : Mode, reset : bool, update : bool) -> Records {
+		let (file_name, ranking, size) = match mode {
+			Mode::Endless => (String::from(HIGHSCORE_FILE), Ranking::Score, 1),
+			Mode::Marathon {lines} => (format!(".tetris-marathon-{}", lines), Ranking::Score, 1),
+			Mode::Sprint {lines} => (format!(".tetris-sprint-{}", lines), Ranking::Time, 1),
+			Mode::Ultra {time, level} => (format!(".tetris-ultra-{}-{}", time.as_secs(), level), Ranking::Score, 5),
+			Mode::Zen {level} => (format!(".tetris-zen-{}", level), Ranking::Score, 1),
+			Mode::Dig {rows, messiness} => (format!(".tetris-dig-{}-{}", rows, messiness), Ranking::Time, 1),
+			Mode::Master {..} => (String::from(".tetris-master"), Ranking::Score, 1),
+			Mode::Survival {interval} => (format!(".tetris-survival-{}", interval.as_millis()), Ranking::Survival, 1)
+		};
+		let entries = if reset {vec![]} else {read_highscore(&file_name)};
+		Records {file_name, ranking, size, entries, update}
+	}
+
+	fn format(&self, value : usize) -> String {
+		match self.ranking {
+			Ranking::Score => value.to_string(),
+			Ranking::Time | Ranking::Survival => format_time(Duration::from_millis(value as u64))
+		}
+	}
+
+	/// The line shown in the side panel. The highscore of games without an end follows the current score.
+	fn panel(&self, game : &Game) -> String {
+		let best = self.entries.first().copied();
+		match game.settings().mode {
+			Mode::Endless | Mode::Zen {..} => format!("Highscore: {}", best.unwrap_or(0).max(game.score())),
+			_ => format!("Best: {}", best.map(|best| self.format(best)).unwrap_or_else(|| String::from("-")))
+		}
+	}
+
+	/// Adds a value to the table, returning its place if it made it in.
+	fn add(&mut self, value : usize) -> Option<usize> {
+		let place = match self.ranking {
+			Ranking::Score | Ranking::Survival => self.entries.iter().position(|&entry| value > entry),
+			Ranking::Time => self.entries.iter().position(|&entry| value < entry)
+		}.unwrap_or(self.entries.len());
+		if place >= self.size {
+			return None;
+		}
+		self.entries.insert(place, value);
+		self.entries.truncate(self.size);
+		if self.update {
+			write_highscore(&self.file_name, &self.entries);
+		}
+		Some(place)
+	}
+
+	/// Records a game that ended and describes how it compares for the game over screen.
+	/// Only modes that end by topping out anyway count it.
+	fn finish(&mut self, game : &Game) -> Vec<String> {
+		let mut lines = vec![format!("Time : {}", format_time(game.time()))];
+		let counts = game.state() == GameState::Finished || matches!(game.settings().mode, Mode::Endless | Mode::Marathon {..} | Mode::Master {..} | Mode::Survival {..});
+		let value = match self.ranking {
+			Ranking::Score => game.score(),
+			Ranking::Time | Ranking::Survival => game.time().as_millis() as usize
+		};
+		let place = if counts {self.add(value)} else {None};
+		if self.size == 1 {
+			match (place, self.entries.first()) {
+				(Some(0), _) if value > 0 => lines.push(String::from("New personal best!")),
+				(_, Some(&best)) => lines.push(format!("Personal best : {}", self.format(best))),
+				_ => ()
+			}
+			return lines;
+		}
+		lines.push(String::from("Personal bests :"));
+		for (i, &entry) in self.entries.iter().enumerate() {
+			let marker = if place == Some(i) {" <"} else {""};
+			lines.push(format!("  {}. {}{}", i + 1, self.format(entry), marker));
+		}
+		lines
+	}
+}
+
+
+/// A puzzle file or a directory of them played in order of their file names. How many have
+/// been solved is kept in the home directory so that the next run picks up from there.
+struct Puzzles {
+	key : String,
+	puzzles : Vec<(PathBuf, Puzzle)>,
+	current : usize
+}
+
+impl Puzzles {
+	fn load(path : &Path) -> io::Result<Puzzles> {
+		let key = fs::canonicalize(path)?.to_string_lossy().into_owned();
+		let mut files = if path.is_dir() {
+			fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()?
+		} else {
+			vec![path.to_path_buf()]
+		};
+		files.retain(|file| file.is_file() && !file.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')));
+		files.sort();
+		let puzzles = files.into_iter().map(|file| match Puzzle::load(&file) {
+			Ok(puzzle) => Ok((file, puzzle)),
+			Err(e) => Err(io::Error::new(e.kind(), format!("{} : {}", file.display(), e)))
+		}).collect::<io::Result<Vec<_>>>()?;
+		if puzzles.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::NotFound, "No puzzles found"));
+		}
+		let current = Puzzles::read_progress().into_iter()
+			.find(|(_, saved)| *saved == key)
+			.map_or(0, |(solved, _)| solved % puzzles.len());
+		Ok(Puzzles {key, puzzles, current})
+	}
+
+	/// Reads the progress file, made of lines with the number of solved puzzles and the path they belong to.
+	fn read_progress() -> Vec<(usize, String)> {
+		let Some(path) = dirs::home_dir().map(|home| home.join(PUZZLE_PROGRESS_FILE)) else {
+			return vec![];
+		};
+		fs::read_to_string(path).unwrap_or_default().lines().filter_map(|line| {
+			let (solved, key) = line.split_once('\t')?;
+			Some((solved.parse().ok()?, String::from(key)))
+		}).collect()
+	}
+
+	fn save_progress(&self) {
+		let Some(path) = dirs::home_dir().map(|home| home.join(PUZZLE_PROGRESS_FILE)) else {
+			return;
+		};
+		let mut progress = Puzzles::read_progress();
+		progress.retain(|(_, key)| *key != self.key);
+		progress.push((self.current, self.key.clone()));
+		let lines : Vec<String> = progress.iter().map(|(solved, key)| format!("{}\t{}\n", solved, key)).collect();
+		let _ = fs::write(path, lines.concat());
+	}
+
+	fn game(&self, settings : &Settings) -> Game {
+		Game::with_puzzle(settings.clone(), &self.puzzles[self.current].1)
+	}
+
+	fn panel(&self) -> String {
+		format!("Puzzle {}/{}: {}", self.current + 1, self.puzzles.len(), self.puzzles[self.current].1.goal())
+	}
+
+	/// Moves on to the next puzzle if this one was solved, starting over after the last one.
+	/// Returns the lines for the game over screen and what pressing R does.
+	fn finish(&mut self, game : &Game) -> (Vec<String>, &'static str) {
+		let name = self.puzzles[self.current].0.file_stem().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+		let mut lines = vec![format!("Puzzle {}/{} : {}", self.current + 1, self.puzzles.len(), name)];
+		if game.state() != GameState::Finished {
+			lines.push(format!("Goal : {}", self.puzzles[self.current].1.goal()));
+			return (lines, "to try again");
+		}
+		self.current += 1;
+		let again = if self.current == self.puzzles.len() {
+			lines.push(String::from("All puzzles solved!"));
+			self.current = 0;
+			"to start over"
+		} else {
+			"for the next puzzle"
+		};
+		self.save_progress();
+		(lines, again)
+	}
+}
 
 pub fn start() -> crossterm::Result<()> {
-	
-	let use_color = !env::args().any(|s| s == "--no-color");
-	let inverse_rotation = env::args().any(|s| s == "--inverse-rotation");
+
 	let update_highscore = !env::args().any(|s| s == "--no-highscore");
 	let reset_highscore = env::args().any(|s| s == "--reset-highscore");
+	let options = Options {
+		use_color : !env::args().any(|s| s == "--no-color"),
+		show_ghost : !env::args().any(|s| s == "--no-ghost"),
+		inverse_rotation : env::args().any(|s| s == "--inverse-rotation"),
+		hold_key : arg_value("--hold-key").and_then(|s| s.chars().next()).unwrap_or('c'),
+		preview : arg_value("--next").and_then(|s| s.parse().ok()).unwrap_or(5).clamp(1, 6)
+	};
+	if BOUND_KEYS.contains(&options.hold_key.to_ascii_lowercase()) {
+		println!("The hold key cannot be '{}', that key is already bound", options.hold_key);
+		return Ok(());
+	}
+	let mut settings = Settings {
+		width : arg_value("--width").and_then(|s| s.parse().ok()).unwrap_or(DEFAULT_WIDTH).clamp(4, 40),
+		height : arg_value("--height").and_then(|s| s.parse().ok()).unwrap_or(DEFAULT_HEIGHT).clamp(4, 40),
+		hold : !env::args().any(|s| s == "--no-hold"),
+		lock_mode : match arg_value("--lock-mode").as_deref() {
+			Some("infinite") => LockMode::Infinite,
+			Some("classic") => LockMode::Classic,
+			Some(_) => LockMode::Extended,
+			// Master mode follows TGM, where only falling resets the lock delay.
+			None if arg_value("--mode").as_deref() == Some("master") => LockMode::Classic,
+			None => LockMode::Extended
+		},
+		lock_delay : Duration::from_millis(arg_value("--lock-delay").and_then(|s| s.parse().ok()).unwrap_or(500)),
+		lock_resets : arg_value("--lock-resets").and_then(|s| s.parse().ok()).unwrap_or(15),
+		das : Duration::from_millis(arg_value("--das").and_then(|s| s.parse().ok()).unwrap_or(167)),
+		arr : Duration::from_millis(arg_value("--arr").and_then(|s| s.parse().ok()).unwrap_or(33)),
+		soft_drop_factor : match arg_value("--soft-drop").as_deref() {
+			Some("instant") => 0,
+			factor => factor.and_then(|s| s.parse().ok()).unwrap_or(20)
+		},
+		randomizer : match arg_value("--randomizer").as_deref() {
+			Some("bag14") => Randomizer::Bag14,
+			Some("random") => Randomizer::Random,
+			Some("nes") => Randomizer::Nes,
+			Some("tgm") => Randomizer::History {retries : 4},
+			Some("tgm2") => Randomizer::History {retries : 6},
+			_ => Randomizer::Bag7
+		},
+		rotation : match arg_value("--rotation").as_deref() {
+			Some("ars") => RotationSystem::Ars,
+			Some("nrs") => RotationSystem::Nrs,
+			Some("sega") => RotationSystem::Sega,
+			Some(_) => RotationSystem::Srs,
+			// Master mode plays with the rotation of TGM unless another one is picked.
+			None if arg_value("--mode").as_deref() == Some("master") => RotationSystem::Ars,
+			None => RotationSystem::Srs
+		},
+		seed : arg_value("--seed").and_then(|s| s.parse().ok()),
+		mode : match arg_value("--mode").as_deref() {
+			Some("marathon") => Mode::Marathon {lines : arg_value("--lines").and_then(|s| s.parse().ok()).unwrap_or(150).max(1)},
+			Some("sprint") => Mode::Sprint {lines : arg_value("--lines").and_then(|s| s.parse().ok()).unwrap_or(40).max(1)},
+			Some("master") => Mode::Master {
+				are : Duration::from_millis(arg_value("--are").and_then(|s| s.parse().ok()).unwrap_or(450)),
+				line_clear_delay : Duration::from_millis(arg_value("--line-clear-delay").and_then(|s| s.parse().ok()).unwrap_or(667))
+			},
+			Some("survival") => Mode::Survival {
+				interval : Duration::from_millis(arg_value("--interval").and_then(|s| s.parse().ok()).unwrap_or(10_000).max(100))
+			},
+			Some("dig") => Mode::Dig {
+				rows : arg_value("--rows").and_then(|s| s.parse().ok()).unwrap_or(10).max(1),
+				messiness : arg_value("--messiness").and_then(|s| s.parse().ok()).unwrap_or(100).min(100)
+			},
+			Some("zen") => Mode::Zen {level : arg_value("--level").and_then(|s| s.parse().ok()).unwrap_or(1).clamp(1, 29)},
+			Some("ultra") => Mode::Ultra {
+				time : Duration::from_secs(arg_value("--time").and_then(|s| s.parse().ok()).unwrap_or(120).max(1)),
+				level : arg_value("--level").and_then(|s| s.parse().ok()).unwrap_or(1).clamp(1, 29)
+			},
+			_ => Mode::Endless
+		},
+		start_level : arg_value("--start-level").and_then(|s| s.parse().ok()).unwrap_or(1).clamp(1, 29),
+		..Settings::default()
+	};
+	// Leave at least one empty row above the garbage.
+	if let Mode::Dig {rows, ..} = &mut settings.mode {
+		*rows = (*rows).min(settings.height - 1);
+	}
 
-	let mut rng = rand::thread_rng();
 	terminal::enable_raw_mode()?;
-	execute!(stdout(), 
+	execute!(stdout(),
 		terminal::EnterAlternateScreen,
 		cursor::Hide,
 		terminal::Clear(ClearType::All)
 	)?;
-	
-	let mut highscore = if reset_highscore {0} else {read_highscore()};
-	
-	let mut bag = [BlockType::I, BlockType::J, BlockType::L, BlockType::O, BlockType::S, BlockType::T, BlockType::Z];
-	let mut bag_index = 1;
-	bag.shuffle(&mut rng);
-
-	let mut board : [[Option<Color>; WIDTH]; HEIGHT] = [[None; WIDTH]; HEIGHT];
-	
-	let mut block = Some(create_block(bag[0]));
-	let mut next_block = create_block(bag[1]);
-
-	let mut delay = Duration::from_millis(800);
-	let lock_delay = Duration::from_millis(500);
-	let soft_drop_delay = Duration::from_millis(100);
-	let mut active_delay;
-
-	let mut time;
-
-	let mut running = true;
-	let mut soft_drop = false;
-	let mut soft_drop_instant = Instant::now();
-	let soft_drop_duration = Duration::from_millis(100);
-	
-	let mut score = 0;
-	let mut line_clears = 0;
-	let mut level = 1;
-
-	let mut lock_actions = None;
-	print_ui(&next_block, highscore, score, line_clears, level, delay, use_color)?;
-	while running {
-		print_board(&board, &block, use_color)?;
-		time = Instant::now();
-		if soft_drop && soft_drop_instant.elapsed() >= soft_drop_duration { // This does not work very well..
-			soft_drop = false;
+	// Terminals without the kitty keyboard protocol ignore this, and it is not needed on Windows.
+	let _ = execute!(stdout(), PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES));
+
+	if let Some(path) = arg_value("--replay") {
+		let result = Replay::load(&path).and_then(|replay| play_replay(replay, &options));
+		let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+		execute!(stdout(), terminal::LeaveAlternateScreen, cursor::Show)?;
+		terminal::disable_raw_mode()?;
+		if let Err(e) = result {
+			println!("Could not read replay {} : {}", path, e);
 		}
-		if soft_drop {
-			active_delay = soft_drop_delay
-		} else if let Some(_) = lock_actions {
-			active_delay = lock_delay;
-		} else {
-			active_delay = delay;
+		return Ok(());
+	}
+
+	let mut puzzles = None;
+	if let Some(path) = arg_value("--puzzle") {
+		match Puzzles::load(Path::new(&path)) {
+			Ok(loaded) => puzzles = Some(loaded),
+			Err(e) => {
+				let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+				execute!(stdout(), terminal::LeaveAlternateScreen, cursor::Show)?;
+				terminal::disable_raw_mode()?;
+				println!("Could not read puzzle {} : {}", path, e);
+				return Ok(());
+			}
 		}
-		loop {
-                        let passed = time.elapsed();
-                        if passed >= active_delay {
-                            break;
-                        }
-
-			match handle_key(&board, &mut block, !inverse_rotation) {
-				KeyAction::Exit => {
-					running = false;
-					break;
-				},
+	}
+
+	let mut records = Records::new(settings.mode, reset_highscore, update_highscore);
+	// Puzzles are not recorded, a replay cannot rebuild their board.
+	let replay_dir = if puzzles.is_some() {None} else {replay_dir()};
+	let controls = game_controls(&options, settings.hold);
+	let new_game = |puzzles : &Option<Puzzles>| match puzzles {
+		Some(puzzles) => puzzles.game(&settings),
+		None => Game::with_settings(settings.clone())
+	};
+	let panel = |records : &Records, puzzles : &Option<Puzzles>, game : &Game| match puzzles {
+		Some(puzzles) => puzzles.panel(),
+		None => records.panel(game)
+	};
+
+	let mut game = new_game(&puzzles);
+	let mut next_tick = Instant::now() + TICK;
+	let mut messages = Messages::new();
+	let mut session = Statistics::default();
+	// Whether the terminal reports key releases, otherwise every press is a single tap.
+	let mut releases = false;
+
+	print_game(&game, &panel(&records, &puzzles, &game), &messages, &controls, &options)?;
+	loop {
+		let now = Instant::now();
+		let mut changed = if now >= next_tick {
+			next_tick += TICK;
+			let changed = game.tick();
+			print_time(&game, options.use_color)?;
+			changed
+		} else if event::poll(next_tick - now)? {
+			match handle_key(event::read()?, &options, releases) {
+				KeyAction::Exit => break,
 				KeyAction::Pause => {
-					let remaining = active_delay - passed;
+					let remaining = next_tick.saturating_duration_since(Instant::now());
 					pause()?;
-					print_board(&board, &block, use_color)?;
-					print_ui(&next_block, highscore, score, line_clears, level, delay, use_color)?;
-					time = Instant::now() - remaining;
+					next_tick = Instant::now() + remaining;
+					game.release(Input::MoveLeft);
+					game.release(Input::MoveRight);
+					game.release(Input::SoftDrop);
+					true
 				},
-				KeyAction::Move(true) => {
-					if let Some(actions_left) = lock_actions {
-						let b = block.as_mut().unwrap();
-						b.y_pos += 1;
-						if !overlapps(&board, b) {
-							active_delay = if soft_drop {soft_drop_delay} else {delay}
-						} else if actions_left > 0 {
-							lock_actions = Some(actions_left - 1);
-							time = Instant::now();
-						}
-						b.y_pos -= 1;
+				KeyAction::Press(input) => {
+					let changed = game.step(input);
+					if !releases {
+						game.release(input);
 					}
-					print_board(&board, &block, use_color)?;
+					changed
 				},
-				KeyAction::Drop(false) => {
-					active_delay = soft_drop_delay;
-					soft_drop_instant = Instant::now();
-					soft_drop = true;
+				KeyAction::Release(input) => {
+					releases = true;
+					game.release(input);
+					false
 				},
-				KeyAction::Drop(true) => {
-					lock_actions = None;
-					let rows = freeze(&mut board, &block.unwrap());
-					add_score(rows, &mut level, &mut highscore, &mut score, &mut line_clears, &mut delay);
-					block = None;
-					break;
-				}
-				_ => ()
-			};
-		}
-		if let Some(_) = lock_actions {
-			if !move_block(&board, block.as_mut().unwrap(), 0, 1) {
-				let rows = freeze(&mut board, &block.unwrap());
-				add_score(rows, &mut level, &mut highscore, &mut score, &mut line_clears, &mut delay);
-				block = None;
-			}
-			lock_actions = None;
-		} else if let Some(b) = block.as_mut() {
-			if !move_block(&board, b, 0, 1) { // Maybe move back?
-				lock_actions = Some(15);
+				KeyAction::None => false
 			}
 		} else {
-			bag_index += 1;
-			if bag_index == bag.len() {
-				bag_index = 0;
-				bag.shuffle(&mut rng);
+			false
+		};
+		changed |= messages.update(&game);
+		if game.state() != GameState::Running {
+			session.merge(game.statistics());
+			let saved = replay_dir.as_deref().and_then(|dir| save_replay(&game, dir));
+			let (mut result, again) = match &mut puzzles {
+				Some(puzzles) => puzzles.finish(&game),
+				None => (records.finish(&game), "to play again")
+			};
+			if let Some(path) = saved {
+				result.push(format!("Replay : {}", path.display()));
 			}
-			if overlapps(&board, &next_block) {
-				if let Ok(true) = print_game_over(score, level) {
-					bag.shuffle(&mut rng);
-					next_block = create_block(bag[0]);
-					block = Some(create_block(bag[1]));
-					bag_index = 1;
-					score = 0;
-					level = 1;
-					line_clears = 0;
-					delay = Duration::from_millis(800);
-					soft_drop = false;
-					board = [[None; WIDTH]; HEIGHT];
-					print_ui(&next_block, highscore, score, line_clears, level, delay, use_color)?;
-					continue;
-				} else {
-					break;
-				}
+			if print_game_over(&game, &result, again, &session)? {
+				game = new_game(&puzzles);
+				next_tick = Instant::now() + TICK;
+				messages = Messages::new();
+			} else {
+				break;
 			}
-			block = Some(next_block);
-			next_block = create_block(bag[bag_index]);
-			print_ui(&next_block, highscore, score, line_clears, level, delay, use_color)?;
-		}	
+		} else if !changed {
+			continue;
+		}
+		print_game(&game, &panel(&records, &puzzles, &game), &messages, &controls, &options)?;
 	}
-	if update_highscore {
-		write_highscore(highscore);
+	// A game that was left early is recorded too, unless it never got an input.
+	if game.state() == GameState::Running && !game.replay().events().is_empty() && puzzles.is_none() {
+		if let Mode::Endless | Mode::Zen {..} = settings.mode {
+			records.add(game.score());
+		}
+		if let Some(dir) = &replay_dir {
+			save_replay(&game, dir);
+		}
 	}
-	
+
+	let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
 	execute!(stdout(), terminal::LeaveAlternateScreen, cursor::Show)?;
 	terminal::disable_raw_mode()?;
 	Ok(())
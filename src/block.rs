@@ -0,0 +1,168 @@
+use crate::board::{Board, Cell};
+use crate::rotation::RotationSystem;
+
+/// The seven tetromino shapes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockType {
+	I, J, L, O, S, T, Z
+}
+
+impl BlockType {
+	/// The upper case letter naming the block type.
+	pub fn letter(&self) -> char {
+		match self {
+			BlockType::I => 'I',
+			BlockType::J => 'J',
+			BlockType::L => 'L',
+			BlockType::O => 'O',
+			BlockType::S => 'S',
+			BlockType::T => 'T',
+			BlockType::Z => 'Z'
+		}
+	}
+
+	/// The block type named by a letter, in either case.
+	pub fn from_letter(letter : char) -> Option<BlockType> {
+		match letter.to_ascii_uppercase() {
+			'I' => Some(BlockType::I),
+			'J' => Some(BlockType::J),
+			'L' => Some(BlockType::L),
+			'O' => Some(BlockType::O),
+			'S' => Some(BlockType::S),
+			'T' => Some(BlockType::T),
+			'Z' => Some(BlockType::Z),
+			_ => None
+		}
+	}
+}
+
+/// A tetromino with its rotation states and position on the board.
+#[derive(Debug, Clone)]
+pub struct Block {
+	tiles : [Vec<Vec<Option<Cell>>>; 4],
+	pub(crate) rotation : usize,
+	pub(crate) x_pos : isize,
+	pub(crate) y_pos : isize,
+	name : BlockType,
+	system : RotationSystem
+}
+
+impl Block {
+	/// The tiles of the current rotation state, indexed `[y][x]` relative to `position`.
+	pub fn tiles(&self) -> &[Vec<Option<Cell>>] {
+		&self.tiles[self.rotation]
+	}
+
+	/// The board coordinates of the top left corner of `tiles`.
+	pub fn position(&self) -> (isize, isize) {
+		(self.x_pos, self.y_pos)
+	}
+
+	/// The current rotation state, 0 being the spawn orientation.
+	pub fn rotation(&self) -> usize {
+		self.rotation
+	}
+
+	pub fn kind(&self) -> BlockType {
+		self.name
+	}
+
+	pub fn rotation_system(&self) -> RotationSystem {
+		self.system
+	}
+}
+
+fn cell_map(tiles : [Vec<Vec<bool>>; 4], cell : Cell) -> [Vec<Vec<Option<Cell>>>; 4] {
+	tiles.map(|shape|
+		shape.into_iter().map(|row| row.into_iter().map(|b|
+			if b {Some(cell)} else {None}).collect::<Vec<Option<Cell>>>()
+		).collect::<Vec<Vec<Option<Cell>>>>()
+	)
+}
+
+/// Creates a block of the given type in the spawn orientation of `system`, positioned at the top left corner.
+pub fn create_block(name : BlockType, system : RotationSystem) -> Block {
+	let tiles = cell_map(system.rules().states(name), Cell::Block(name));
+	Block {tiles, rotation : 0, x_pos : 0, y_pos : 0, name, system}
+}
+
+/// Creates a block of the given type in its spawn position on `board`, its lowest row being
+/// the last one above the visible playfield.
+pub(crate) fn spawn_block(name : BlockType, system : RotationSystem, board : &Board) -> Block {
+	let mut block = create_block(name, system);
+	let bottom = block.tiles().iter().rposition(|row| row.iter().any(|tile| tile.is_some())).unwrap();
+	block.x_pos = ((board.width() - block.tiles()[0].len()) / 2) as isize;
+	block.y_pos = board.hidden_rows().saturating_sub(bottom + 1) as isize;
+	block
+}
+
+/// Rotates the block using the kicks of its rotation system, returning the index of the kick that was used.
+pub(crate) fn rotate(board : &Board, block : &mut Block, clockwise : bool) -> Option<usize> {
+	block.rotation = if clockwise {(block.rotation + 1) % 4} else {(block.rotation + 3) % 4};
+	if let BlockType::O = block.name {
+		return None;
+	}
+	let kicks = block.system.rules().kicks(board, block, clockwise);
+	let x = block.x_pos;
+	let y = block.y_pos;
+	for (index, kick) in kicks.iter().enumerate() {
+		block.x_pos = x + kick.0;
+		block.y_pos = y + kick.1;
+		if !overlapps(board, block) {
+			return Some(index);
+		}
+	}
+
+	block.x_pos = x;
+	block.y_pos = y;
+	block.rotation = if clockwise {(block.rotation + 3) % 4} else {(block.rotation + 1) % 4};
+	None
+}
+
+pub(crate) fn move_block(board : &Board, block : &mut Block, dx : isize, dy : isize) -> bool {
+	block.x_pos += dx;
+	block.y_pos += dy;
+	if overlapps(board, block) {
+		block.x_pos -= dx;
+		block.y_pos -= dy;
+		return false;
+	}
+	true
+}
+
+pub(crate) fn in_bounds(board : &Board, block : &Block, x : usize, y : usize) -> bool {
+	let x = block.x_pos + x as isize;
+	let y = block.y_pos + y as isize;
+	x >= 0 && y >= 0 && (x as usize) < board.width() && (y as usize) < board.height()
+}
+
+pub(crate) fn overlapps(board : &Board, block : &Block) -> bool {
+	let tiles = block.tiles();
+	for y in 0..tiles.len() {
+		for x in 0..tiles[y].len() {
+			if tiles[y][x].is_some() && (!in_bounds(board, block, x, y) ||
+				board[(y as isize + block.y_pos) as usize][(x as isize + block.x_pos) as usize].is_some())
+			{
+				return true;
+			}
+		}
+	}
+	false
+}
+
+/// Whether the four cells diagonal to the center of a T block are occupied, in clockwise
+/// order starting with the two on the side it points towards. The walls and floor count as occupied.
+pub(crate) fn corners(board : &Board, block : &Block) -> [bool; 4] {
+	const DIRECTIONS : [(isize, isize); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+	let tiles = block.tiles();
+	let filled = |x : isize, y : isize| x >= 0 && y >= 0 &&
+		tiles.get(y as usize).and_then(|row| row.get(x as usize)).is_some_and(|tile| tile.is_some());
+	// The center is the tile with three neighbours, the T points away from the missing one.
+	let (cx, cy) = (0..tiles.len() as isize).flat_map(|y| (0..tiles[0].len() as isize).map(move |x| (x, y)))
+		.find(|&(x, y)| filled(x, y) && DIRECTIONS.iter().filter(|(dx, dy)| filled(x + dx, y + dy)).count() == 3)
+		.unwrap();
+	let missing = DIRECTIONS.iter().position(|(dx, dy)| !filled(cx + dx, cy + dy)).unwrap();
+	let corners = [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(dx, dy)| board.is_occupied(block.x_pos + cx + dx, block.y_pos + cy + dy));
+	let front = (missing + 2) % 4;
+	[0, 1, 2, 3].map(|i| corners[(front + i) % 4])
+}
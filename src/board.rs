@@ -0,0 +1,203 @@
+use std::{fmt, ops::{Index, IndexMut}};
+
+use crate::block::{Block, BlockType, in_bounds};
+
+/// Width of the playfield in cells unless configured otherwise.
+pub const DEFAULT_WIDTH : usize = 10;
+/// Height of the visible playfield in cells unless configured otherwise.
+pub const DEFAULT_HEIGHT : usize = 20;
+/// Number of rows above the visible playfield unless configured otherwise.
+pub const DEFAULT_HIDDEN_ROWS : usize = 20;
+
+/// What fills a cell of the board or of a block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cell {
+	/// Part of a block of the given type.
+	Block(BlockType),
+	/// Part of a garbage row added below the stack.
+	Garbage
+}
+
+/// The frozen cells of the playfield, indexed `[y][x]` with `y = 0` at the top.
+/// The first `hidden_rows` rows are above the visible part of the playfield.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Board {
+	width : usize,
+	hidden_rows : usize,
+	cells : Vec<Vec<Option<Cell>>>
+}
+
+impl Board {
+	/// Creates an empty board. Both visible dimensions need to be at least 4 to fit every block.
+	pub fn new(width : usize, height : usize, hidden_rows : usize) -> Board {
+		assert!(width >= 4 && height >= 4, "A {}x{} board is too small", width, height);
+		Board {width, hidden_rows, cells : vec![vec![None; width]; height + hidden_rows]}
+	}
+
+	/// Reads the text form of a board: one line per row from top to bottom, with `.` for an
+	/// empty cell, the letter of a block type for a cell of that block and `#` for garbage.
+	/// The rows end up at the bottom of a visible playfield of at least `height` rows. Full rows
+	/// are rejected, as they would never be cleared.
+	pub fn parse(text : &str, height : usize, hidden_rows : usize) -> Result<Board, String> {
+		let rows : Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+		let width = rows.first().map_or(0, |row| row.chars().count());
+		if width < 4 {
+			return Err(String::from("A board needs to be at least 4 cells wide"));
+		}
+		let mut board = Board::new(width, height.max(rows.len()).max(4), hidden_rows);
+		let top = board.height() - rows.len();
+		for (y, row) in rows.iter().enumerate() {
+			if row.chars().count() != width {
+				return Err(format!("Row {} is not {} cells wide", y + 1, width));
+			}
+			if !row.contains('.') {
+				return Err(format!("Row {} is already full", y + 1));
+			}
+			for (x, cell) in row.chars().enumerate() {
+				board.cells[top + y][x] = match cell {
+					'.' => None,
+					'#' => Some(Cell::Garbage),
+					letter => Some(Cell::Block(BlockType::from_letter(letter).ok_or(format!("Unknown cell '{}' in row {}", letter, y + 1))?))
+				};
+			}
+		}
+		Ok(board)
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	/// The total number of rows, including the hidden ones.
+	pub fn height(&self) -> usize {
+		self.cells.len()
+	}
+
+	/// The number of rows above the visible playfield.
+	pub fn hidden_rows(&self) -> usize {
+		self.hidden_rows
+	}
+
+	/// All rows, including the hidden ones.
+	pub fn rows(&self) -> &[Vec<Option<Cell>>] {
+		&self.cells
+	}
+
+	/// The rows of the visible playfield. The first one has index `hidden_rows`.
+	pub fn visible_rows(&self) -> &[Vec<Option<Cell>>] {
+		&self.cells[self.hidden_rows..]
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.cells.iter().flatten().all(|tile| tile.is_none())
+	}
+
+	/// The number of rows that still contain garbage.
+	pub fn garbage_rows(&self) -> usize {
+		self.cells.iter().filter(|row| row.contains(&Some(Cell::Garbage))).count()
+	}
+
+	/// Pushes every row up, adding one garbage row at the bottom for each hole column given.
+	/// Returns `false` if a filled cell was pushed out through the top.
+	pub(crate) fn raise(&mut self, holes : &[usize]) -> bool {
+		let mut fits = true;
+		for &hole in holes {
+			let top = self.cells.remove(0);
+			fits &= top.iter().all(|tile| tile.is_none());
+			let mut row = vec![Some(Cell::Garbage); self.width];
+			row[hole.min(self.width - 1)] = None;
+			self.cells.push(row);
+		}
+		fits
+	}
+
+	/// Whether the cell is outside the walls or floor, or already filled. Cells above the top are free.
+	pub fn is_occupied(&self, x : isize, y : isize) -> bool {
+		x < 0 || x >= self.width as isize || y >= self.height() as isize ||
+			(y >= 0 && self.cells[y as usize][x as usize].is_some())
+	}
+}
+
+/// Writes the visible rows in the form read by `Board::parse`.
+impl fmt::Display for Board {
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+		for row in self.visible_rows() {
+			for cell in row {
+				let letter = match cell {
+					None => '.',
+					Some(Cell::Block(kind)) => kind.letter(),
+					Some(Cell::Garbage) => '#'
+				};
+				write!(f, "{}", letter)?;
+			}
+			writeln!(f)?;
+		}
+		Ok(())
+	}
+}
+
+impl Index<usize> for Board {
+	type Output = Vec<Option<Cell>>;
+
+	fn index(&self, y : usize) -> &Self::Output {
+		&self.cells[y]
+	}
+}
+
+impl IndexMut<usize> for Board {
+	fn index_mut(&mut self, y : usize) -> &mut Self::Output {
+		&mut self.cells[y]
+	}
+}
+
+/// Writes `block` into the board and removes any full rows, returning how many were cleared.
+pub(crate) fn freeze(board : &mut Board, block : &Block) -> usize {
+	let tiles = block.tiles();
+	for y in 0..tiles.len() {
+		for x in 0..tiles[y].len() {
+			if !in_bounds(board, block, x, y) {
+				continue;
+			}
+			let tile = tiles[y][x];
+			if tile.is_some() {
+				board[(y as isize + block.y_pos) as usize][(x as isize + block.x_pos) as usize] = tile;
+			}
+		}
+	}
+	let height = board.height();
+	board.cells.retain(|row| row.iter().any(|tile| tile.is_none()));
+	let cleared_rows = height - board.height();
+	for _ in 0..cleared_rows {
+		board.cells.insert(0, vec![None; board.width]);
+	}
+	cleared_rows
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_and_display_round_trip() {
+		let text = "..........\nT.........\nTT..#####.\nTIIII.OOZZ\n";
+		let board = Board::parse(text, 4, 2).unwrap();
+		assert_eq!(board.width(), 10);
+		assert_eq!(board.visible_rows().len(), 4);
+		assert_eq!(board.garbage_rows(), 1);
+		assert_eq!(board.to_string(), text);
+	}
+
+	#[test]
+	fn parse_places_rows_at_the_bottom() {
+		let board = Board::parse("....\nJJ#.\n", 5, 1).unwrap();
+		assert_eq!(board.to_string(), "....\n....\n....\n....\nJJ#.\n");
+	}
+
+	#[test]
+	fn parse_rejects_bad_rows() {
+		assert!(Board::parse("....\n...\n", 4, 0).is_err());
+		assert!(Board::parse("...\n", 4, 0).is_err());
+		assert!(Board::parse("..x.\n", 4, 0).is_err());
+		assert_eq!(Board::parse("....\nJJ#O\n", 4, 0).unwrap_err(), "Row 2 is already full");
+	}
+}
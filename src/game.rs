@@ -0,0 +1,1254 @@
+use std::{fmt, time::Duration};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::block::{Block, BlockType, spawn_block, rotate, move_block, overlapps, corners};
+use crate::board::{Board, DEFAULT_WIDTH, DEFAULT_HEIGHT, DEFAULT_HIDDEN_ROWS, freeze};
+use crate::puzzle::{Goal, Puzzle};
+use crate::queue::PieceQueue;
+use crate::randomizer::Randomizer;
+use crate::replay::{Event, Replay};
+use crate::rotation::RotationSystem;
+
+/// The length of one game tick. `Game::tick` should be called this often.
+pub const TICK : Duration = Duration::from_micros(16_667);
+/// The total number of lines needed to get past each level. There is no level after the last one.
+const LEVEL_LINES : [usize; 28] = [
+	10, 30, 70, 120, 180, 250, 330, 420, 520, 620, 720, 820, 920, 1020,
+	1120, 1230, 1350, 1480, 1620, 1770, 1930, 2100, 2280, 2470, 2670, 2870, 3070, 3270
+];
+/// How much shorter the wait for the next garbage row gets with every row in `Mode::Survival`.
+const SURVIVAL_SPEEDUP : f64 = 0.97;
+/// The shortest wait between garbage rows in `Mode::Survival`.
+const SURVIVAL_MIN_INTERVAL : Duration = Duration::from_millis(500);
+/// The level that finishes `Mode::Master`.
+const MASTER_LEVELS : usize = 999;
+/// Gravity in `Mode::Master` in 1/256 rows per tick, each from the level it starts at.
+/// 256 is one row per tick, 1G, and 5120 is 20G which drops blocks straight onto the stack.
+const MASTER_GRAVITY : [(usize, u32); 30] = [
+	(0, 4), (30, 6), (35, 8), (40, 10), (50, 12), (60, 16), (70, 32), (80, 48), (90, 64),
+	(100, 80), (120, 96), (140, 112), (160, 128), (170, 144), (200, 4), (220, 32), (230, 64),
+	(233, 96), (236, 128), (239, 160), (243, 192), (247, 224), (251, 256), (300, 512),
+	(330, 768), (360, 1024), (400, 1280), (420, 1024), (450, 768), (500, 5120)
+];
+/// Entry, line clear and lock delay in frames for each section of 100 levels in `Mode::Master`,
+/// as in TGM3. They scale the configured delays, which stand for the first section.
+const MASTER_DELAYS : [(u32, u32, u32); 10] = [
+	(27, 40, 30), (27, 40, 30), (27, 40, 30), (27, 40, 30), (27, 40, 30),
+	(27, 25, 30), (27, 16, 30), (18, 12, 30), (14, 6, 30), (14, 6, 17)
+];
+
+
+/// A player action that can be applied with `Game::step`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Input {
+	MoveLeft,
+	MoveRight,
+	RotateClockwise,
+	RotateCounterClockwise,
+	SoftDrop,
+	HardDrop,
+	Hold
+}
+
+/// Why a game was lost.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Loss {
+	/// A new block overlapped the stack where it spawned.
+	BlockOut,
+	/// A block locked entirely above the visible playfield.
+	LockOut,
+	/// Garbage pushed blocks out through the top of the board.
+	TopOut,
+	/// The goal of a puzzle can no longer be reached.
+	Failed
+}
+
+impl fmt::Display for Loss {
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Loss::BlockOut => write!(f, "Block out"),
+			Loss::LockOut => write!(f, "Lock out"),
+			Loss::TopOut => write!(f, "Top out"),
+			Loss::Failed => write!(f, "Puzzle failed")
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GameState {
+	Running,
+	GameOver(Loss),
+	/// The goal of the mode was reached.
+	Finished
+}
+
+/// The kind of T-spin a lock was, if any.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Spin {
+	None,
+	Mini,
+	Full
+}
+
+/// What a single lock scored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Action {
+	pub rows : usize,
+	pub spin : Spin,
+	/// Whether this was a Tetris or T-spin line clear directly following another one.
+	pub back_to_back : bool,
+	/// How many line clears in a row preceded this one.
+	pub combo : usize,
+	/// Whether the board was left completely empty.
+	pub perfect_clear : bool,
+	pub points : usize
+}
+
+impl fmt::Display for Action {
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+		let rows = ["", "Single", "Double", "Triple", "Tetris"][self.rows];
+		if self.back_to_back {
+			write!(f, "B2B ")?;
+		}
+		match self.spin {
+			Spin::None => write!(f, "{}", rows),
+			Spin::Mini => write!(f, "Mini T-Spin {}", rows),
+			Spin::Full => write!(f, "T-Spin {}", rows)
+		}
+	}
+}
+
+/// Counts of notable clears.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Statistics {
+	pub tetrises : usize,
+	pub t_spins : usize,
+	pub perfect_clears : usize,
+	pub max_combo : usize
+}
+
+impl Statistics {
+	/// Adds the counts of `other` to these, for keeping totals over several games.
+	pub fn merge(&mut self, other : &Statistics) {
+		self.tetrises += other.tetrises;
+		self.t_spins += other.t_spins;
+		self.perfect_clears += other.perfect_clears;
+		self.max_combo = self.max_combo.max(other.max_combo);
+	}
+}
+
+/// When a block resting on the stack locks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LockMode {
+	/// Moving or rotating resets the lock delay, at most `lock_resets` times
+	/// unless the block reaches a row lower than it has been before.
+	Extended,
+	/// Moving or rotating always resets the lock delay.
+	Infinite,
+	/// The lock delay is only reset when the block falls.
+	Classic
+}
+
+/// What ends a game besides topping out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+	/// Goes on until the player tops out.
+	Endless,
+	/// Finished once the given number of lines has been cleared, with the usual level progression.
+	Marathon {lines : usize},
+	/// Finished once the given number of lines has been cleared.
+	Sprint {lines : usize},
+	/// Finished once `time` has run out, with gravity fixed at `level`.
+	Ultra {time : Duration, level : usize},
+	/// Never ends, topping out clears the stack instead. Gravity is fixed at `level`.
+	Zen {level : usize},
+	/// Starts with `rows` rows of garbage, also known as cheese, and is finished once they are
+	/// all cleared. `messiness` is the chance in percent that a hole is in a different column
+	/// than the one in the row above.
+	Dig {rows : usize, messiness : usize},
+	/// Levels go from 0 to 999 as in TGM, rising with every block and every cleared line, with
+	/// gravity up to 20G. `are` is the delay before the next block enters and `line_clear_delay`
+	/// is added to it after a clear. Both get shorter by section of 100 levels, as does the lock delay.
+	Master {are : Duration, line_clear_delay : Duration},
+	/// Garbage rises one row at a time, first after `interval` and then a little faster with
+	/// every row. Goes on until the player tops out.
+	Survival {interval : Duration}
+}
+
+/// Options that are fixed for the duration of a game.
+#[derive(Debug, Clone)]
+pub struct Settings {
+	/// Playfield width in cells, at least 4.
+	pub width : usize,
+	/// Visible playfield height in cells, at least 4.
+	pub height : usize,
+	/// Rows above the visible playfield where blocks spawn and can be stacked.
+	pub hidden_rows : usize,
+	/// Whether the falling block can be swapped into the hold slot.
+	pub hold : bool,
+	pub lock_mode : LockMode,
+	/// How long a block can rest on the stack before it locks.
+	pub lock_delay : Duration,
+	/// How many times moving or rotating can reset the lock delay in `LockMode::Extended`.
+	pub lock_resets : usize,
+	/// Delayed auto shift, how long a move has to be held before it starts repeating.
+	pub das : Duration,
+	/// Auto repeat rate, the time between repeated moves. Zero moves all the way at once.
+	pub arr : Duration,
+	/// How many times faster than gravity a held soft drop falls. Zero drops all the way
+	/// at once without locking.
+	pub soft_drop_factor : u32,
+	pub randomizer : Randomizer,
+	pub rotation : RotationSystem,
+	/// Determines the block sequence and garbage holes. A random seed is picked when `None`.
+	pub seed : Option<u64>,
+	pub mode : Mode,
+	/// The level the game starts at, which sets the initial gravity.
+	pub start_level : usize
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Settings {
+			width : DEFAULT_WIDTH,
+			height : DEFAULT_HEIGHT,
+			hidden_rows : DEFAULT_HIDDEN_ROWS,
+			hold : true,
+			lock_mode : LockMode::Extended,
+			lock_delay : Duration::from_millis(500),
+			lock_resets : 15,
+			das : Duration::from_millis(167),
+			arr : Duration::from_millis(33),
+			soft_drop_factor : 20,
+			randomizer : Randomizer::Bag7,
+			rotation : RotationSystem::Srs,
+			seed : None,
+			mode : Mode::Endless,
+			start_level : 1
+		}
+	}
+}
+
+/// A single game of Tetris, advanced by calling `tick` once every `TICK`.
+pub struct Game {
+	settings : Settings,
+	board : Board,
+	block : Option<Block>,
+	queue : PieceQueue,
+	goal : Option<Goal>,
+	seed : u64,
+	rng : StdRng,
+	ticks : u64,
+	events : Vec<(u64, Event)>,
+	held_block : Option<Block>,
+	hold_used : bool,
+	state : GameState,
+
+	delay : Duration,
+	timer : Duration,
+	fall_units : u32,
+	entry_delay : Duration,
+	garbage_timer : Duration,
+	risen_rows : usize,
+	soft_drop : bool,
+	lock_timer : Option<Duration>,
+	lock_resets : usize,
+	lowest_row : isize,
+	held_left : bool,
+	held_right : bool,
+	shift : Option<Input>,
+	das_timer : Duration,
+	arr_timer : Option<Duration>,
+	last_kick : Option<usize>,
+	last_action : Option<Action>,
+	pieces : usize,
+	combo : Option<usize>,
+	back_to_back : bool,
+	statistics : Statistics,
+
+	score : usize,
+	cleared_rows : usize,
+	level : usize,
+	level_lines : usize
+}
+
+impl Default for Game {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Game {
+	pub fn new() -> Game {
+		Self::with_settings(Settings::default())
+	}
+
+	pub fn with_settings(settings : Settings) -> Game {
+		let seed = settings.seed.unwrap_or_else(rand::random);
+		let queue = PieceQueue::new(settings.randomizer, seed);
+		let board = Board::new(settings.width, settings.height, settings.hidden_rows);
+		let mut game = Game::create(settings, board, queue, seed);
+		if let Mode::Dig {rows, messiness} = game.settings.mode {
+			let holes = game.dig_holes(rows, messiness);
+			game.board.raise(&holes);
+		}
+		game.spawn();
+		game
+	}
+
+	/// Starts a game on the board of the puzzle with only its blocks. The size of the board
+	/// and the goal come from the puzzle, the mode and randomizer of `settings` are ignored.
+	pub fn with_puzzle(settings : Settings, puzzle : &Puzzle) -> Game {
+		let board = puzzle.board.clone();
+		let settings = Settings {
+			width : board.width(),
+			height : board.visible_rows().len(),
+			hidden_rows : board.hidden_rows(),
+			mode : Mode::Endless,
+			..settings
+		};
+		let seed = settings.seed.unwrap_or_else(rand::random);
+		let mut game = Game::create(settings, board, PieceQueue::fixed(&puzzle.pieces), seed);
+		game.goal = Some(puzzle.goal);
+		game.spawn();
+		game
+	}
+
+	fn create(settings : Settings, board : Board, queue : PieceQueue, seed : u64) -> Game {
+		let level = match settings.mode {
+			Mode::Ultra {level, ..} | Mode::Zen {level} => level.max(1),
+			Mode::Master {..} => 0,
+			_ => settings.start_level.max(1)
+		};
+		// Starting higher counts as having cleared the lines of the levels before.
+		let level_lines = level.checked_sub(2).and_then(|i| LEVEL_LINES.get(i)).copied().unwrap_or(0);
+		Game {
+			settings,
+			block : None,
+			board,
+			queue,
+			goal : None,
+			seed,
+			// Garbage uses its own stream so that it never changes the block sequence.
+			rng : StdRng::seed_from_u64(!seed),
+			ticks : 0,
+			events : vec![],
+			held_block : None,
+			hold_used : false,
+			state : GameState::Running,
+			delay : gravity(level),
+			timer : Duration::ZERO,
+			fall_units : 0,
+			entry_delay : Duration::ZERO,
+			garbage_timer : Duration::ZERO,
+			risen_rows : 0,
+			soft_drop : false,
+			lock_timer : None,
+			lock_resets : 0,
+			lowest_row : 0,
+			held_left : false,
+			held_right : false,
+			shift : None,
+			das_timer : Duration::ZERO,
+			arr_timer : None,
+			last_kick : None,
+			last_action : None,
+			pieces : 0,
+			combo : None,
+			back_to_back : false,
+			statistics : Statistics::default(),
+			score : 0,
+			cleared_rows : 0,
+			level,
+			level_lines
+		}
+	}
+
+	pub fn board(&self) -> &Board {
+		&self.board
+	}
+
+	/// The falling block, or `None` between a lock and the next spawn.
+	pub fn active_piece(&self) -> Option<&Block> {
+		self.block.as_ref()
+	}
+
+	/// The upcoming block types, the first one being spawned next.
+	pub fn next_queue(&self) -> impl Iterator<Item = BlockType> + '_ {
+		self.queue.peek()
+	}
+
+	/// The seed that determines the block sequence and garbage holes of this game.
+	pub fn seed(&self) -> u64 {
+		self.seed
+	}
+
+	/// How many ticks the game has been running for.
+	pub fn ticks(&self) -> u64 {
+		self.ticks
+	}
+
+	/// How long the game has been running for, counted in ticks so it is not affected by pauses.
+	pub fn time(&self) -> Duration {
+		TICK * self.ticks as u32
+	}
+
+	/// How much time is left in `Mode::Ultra`.
+	pub fn time_left(&self) -> Option<Duration> {
+		match self.settings.mode {
+			Mode::Ultra {time, ..} => Some(time.saturating_sub(self.time())),
+			_ => None
+		}
+	}
+
+	/// How long until the next garbage row rises in `Mode::Survival`.
+	pub fn next_garbage(&self) -> Option<Duration> {
+		Some(self.garbage_interval()?.saturating_sub(self.garbage_timer))
+	}
+
+	fn garbage_interval(&self) -> Option<Duration> {
+		let Mode::Survival {interval} = self.settings.mode else {
+			return None;
+		};
+		Some(interval.mul_f64(SURVIVAL_SPEEDUP.powi(self.risen_rows as i32)).max(SURVIVAL_MIN_INTERVAL))
+	}
+
+	/// A recording of the game so far that plays back exactly the same. Garbage added from
+	/// outside through `add_garbage` and the board and blocks of a puzzle are not part of it.
+	pub fn replay(&self) -> Replay {
+		let settings = Settings {seed : Some(self.seed), ..self.settings.clone()};
+		Replay {settings, events : self.events.clone(), length : self.ticks}
+	}
+
+	/// Where the falling block would end up if it was hard dropped.
+	pub fn ghost_piece(&self) -> Option<Block> {
+		let mut ghost = self.block.clone()?;
+		while move_block(&self.board, &mut ghost, 0, 1) {}
+		Some(ghost)
+	}
+
+	/// The block in the hold slot, if any.
+	pub fn held_piece(&self) -> Option<&Block> {
+		self.held_block.as_ref()
+	}
+
+	/// Whether `Input::Hold` is currently allowed. Holding is only possible once per spawned block.
+	pub fn can_hold(&self) -> bool {
+		self.settings.hold && !self.hold_used && self.block.is_some()
+	}
+
+	/// The goal when playing a puzzle.
+	pub fn goal(&self) -> Option<Goal> {
+		self.goal
+	}
+
+	pub fn settings(&self) -> &Settings {
+		&self.settings
+	}
+
+	pub fn score(&self) -> usize {
+		self.score
+	}
+
+	pub fn level(&self) -> usize {
+		self.level
+	}
+
+	/// The total number of cleared rows.
+	pub fn lines(&self) -> usize {
+		self.cleared_rows
+	}
+
+	/// The time it takes the falling block to move down one row.
+	pub fn delay(&self) -> Duration {
+		self.delay
+	}
+
+	/// How many rows the falling block moves down per tick, which is below 1 outside of `Mode::Master`.
+	pub fn gravity(&self) -> f64 {
+		match self.settings.mode {
+			Mode::Master {..} => master_gravity(self.level) as f64 / 256.0,
+			_ => TICK.as_secs_f64() / self.delay.as_secs_f64()
+		}
+	}
+
+	/// The number of blocks that have been locked.
+	pub fn pieces(&self) -> usize {
+		self.pieces
+	}
+
+	/// What the most recent lock scored, or `None` if it neither cleared rows nor was a T-spin.
+	pub fn last_action(&self) -> Option<Action> {
+		self.last_action
+	}
+
+	/// The number of consecutive line clears after the first one, or `None` if the last lock cleared nothing.
+	pub fn combo(&self) -> Option<usize> {
+		self.combo
+	}
+
+	/// Whether the last line clear was a Tetris or T-spin, so that the next one can be a back-to-back.
+	pub fn back_to_back(&self) -> bool {
+		self.back_to_back
+	}
+
+	pub fn statistics(&self) -> &Statistics {
+		&self.statistics
+	}
+
+	pub fn state(&self) -> GameState {
+		self.state
+	}
+
+	/// Applies a player input, returning `true` if it had any effect. Inputs are treated as key
+	/// presses, holding a move key auto shifts the block until the input is passed to `release`.
+	pub fn step(&mut self, input : Input) -> bool {
+		if self.state != GameState::Running {
+			return false;
+		}
+		self.events.push((self.ticks, Event::Press(input)));
+		match input {
+			Input::SoftDrop => {
+				self.soft_drop = true;
+				if self.block.is_none() {
+					return false;
+				}
+				self.timer = Duration::ZERO;
+				self.soft_fall()
+			},
+			Input::Hold => self.hold(),
+			Input::HardDrop => {
+				let Some(block) = self.block.as_mut() else {
+					return false;
+				};
+				while move_block(&self.board, block, 0, 1) {
+					self.last_kick = None;
+				}
+				self.lock();
+				self.timer = Duration::ZERO;
+				// The next block comes in right away, only master mode waits for the entry delay.
+				if self.master_delays().is_none() {
+					self.spawn();
+				}
+				true
+			},
+			Input::MoveLeft | Input::MoveRight => {
+				if input == Input::MoveLeft {
+					self.held_left = true;
+				} else {
+					self.held_right = true;
+				}
+				self.charge(Some(input));
+				self.act(input)
+			},
+			Input::RotateClockwise | Input::RotateCounterClockwise => self.act(input)
+		}
+	}
+
+	/// Tells the game that the key for `input` is no longer held.
+	pub fn release(&mut self, input : Input) {
+		if self.state != GameState::Running {
+			return;
+		}
+		self.events.push((self.ticks, Event::Release(input)));
+		match input {
+			Input::SoftDrop => self.soft_drop = false,
+			Input::MoveLeft => {
+				self.held_left = false;
+				if self.shift == Some(Input::MoveLeft) {
+					self.charge(if self.held_right {Some(Input::MoveRight)} else {None});
+				}
+			},
+			Input::MoveRight => {
+				self.held_right = false;
+				if self.shift == Some(Input::MoveRight) {
+					self.charge(if self.held_left {Some(Input::MoveLeft)} else {None});
+				}
+			},
+			_ => ()
+		}
+	}
+
+	/// Moves or rotates the falling block, resetting the lock delay as the lock mode allows.
+	fn act(&mut self, input : Input) -> bool {
+		let Some(block) = self.block.as_mut() else {
+			return false;
+		};
+		let moved = match input {
+			Input::MoveLeft => move_block(&self.board, block, -1, 0),
+			Input::MoveRight => move_block(&self.board, block, 1, 0),
+			Input::RotateClockwise | Input::RotateCounterClockwise => {
+				let kick = rotate(&self.board, block, input == Input::RotateClockwise);
+				if kick.is_some() {
+					self.last_kick = kick;
+				}
+				kick.is_some()
+			},
+			_ => unreachable!("{:?} is not a move", input)
+		};
+		if moved && matches!(input, Input::MoveLeft | Input::MoveRight) {
+			self.last_kick = None;
+		}
+		if moved && self.lock_timer.is_some() {
+			match self.settings.lock_mode {
+				LockMode::Infinite => self.lock_timer = Some(Duration::ZERO),
+				LockMode::Extended if self.lock_resets < self.settings.lock_resets => {
+					self.lock_resets += 1;
+					self.lock_timer = Some(Duration::ZERO);
+				},
+				_ => ()
+			}
+		}
+		moved
+	}
+
+	/// Starts charging the delayed auto shift in the direction of `shift`.
+	fn charge(&mut self, shift : Option<Input>) {
+		self.shift = shift;
+		self.das_timer = Duration::ZERO;
+		self.arr_timer = None;
+	}
+
+	/// Repeats the held move once the auto shift is charged, every `arr` or all the way if it is zero.
+	fn auto_shift(&mut self) -> bool {
+		let Some(input) = self.shift else {
+			return false;
+		};
+		self.das_timer += TICK;
+		if self.das_timer < self.settings.das {
+			return false;
+		}
+		let mut moved = false;
+		let mut arr_timer = self.arr_timer.map_or(self.settings.arr, |timer| timer + TICK);
+		if self.settings.arr.is_zero() {
+			while self.act(input) {
+				moved = true;
+			}
+		} else {
+			while arr_timer >= self.settings.arr {
+				arr_timer -= self.settings.arr;
+				moved |= self.act(input);
+			}
+		}
+		self.arr_timer = Some(arr_timer);
+		moved
+	}
+
+	/// Pushes the stack up with one garbage row for each given hole column. A falling block
+	/// that ends up inside the stack is lifted along with it.
+	pub fn add_garbage(&mut self, holes : &[usize]) {
+		if self.state != GameState::Running {
+			return;
+		}
+		let fits = self.board.raise(holes);
+		if let Some(block) = self.block.as_mut() {
+			for _ in 0..holes.len() {
+				if !overlapps(&self.board, block) {
+					break;
+				}
+				block.y_pos -= 1;
+			}
+		}
+		if !fits || self.block.as_ref().is_some_and(|block| overlapps(&self.board, block)) {
+			self.top_out(Loss::TopOut);
+		}
+	}
+
+	/// Picks the hole columns for `Mode::Dig`, moving the hole with a chance of `messiness` percent.
+	fn dig_holes(&mut self, rows : usize, messiness : usize) -> Vec<usize> {
+		let width = self.board.width();
+		let mut hole = self.rng.gen_range(0..width);
+		let mut holes = vec![hole];
+		for _ in 1..rows {
+			if self.rng.gen_range(0..100) < messiness {
+				// Any other column, so a move always changes the hole.
+				hole = (hole + self.rng.gen_range(1..width)) % width;
+			}
+			holes.push(hole);
+		}
+		holes
+	}
+
+	/// Adds the given number of garbage rows, each with a hole in a random column.
+	pub fn add_random_garbage(&mut self, rows : usize) {
+		let holes : Vec<usize> = (0..rows).map(|_| self.rng.gen_range(0..self.board.width())).collect();
+		self.add_garbage(&holes);
+	}
+
+	/// Advances the game by one `TICK`, returning `true` if the board or falling block changed.
+	pub fn tick(&mut self) -> bool {
+		if self.state != GameState::Running {
+			return false;
+		}
+		self.ticks += 1;
+		if self.time_left() == Some(Duration::ZERO) {
+			self.state = GameState::Finished;
+			return true;
+		}
+		if let Some(interval) = self.garbage_interval() {
+			self.garbage_timer += TICK;
+			if self.garbage_timer >= interval {
+				self.garbage_timer = Duration::ZERO;
+				self.risen_rows += 1;
+				self.add_random_garbage(1);
+				if self.state != GameState::Running {
+					return true;
+				}
+			}
+		}
+		let mut changed = self.auto_shift();
+		if self.soft_drop && self.settings.soft_drop_factor == 0 && self.block.is_some() {
+			changed |= self.soft_fall();
+		}
+		if let Mode::Master {..} = self.settings.mode {
+			changed |= self.master_fall();
+		} else {
+			self.timer += TICK;
+			if self.timer >= self.active_delay() {
+				self.timer = Duration::ZERO;
+				changed |= self.fall();
+			}
+		}
+		let Some(block) = self.block.as_ref() else {
+			return changed;
+		};
+		let mut below = block.clone();
+		if move_block(&self.board, &mut below, 0, 1) {
+			self.lock_timer = None;
+			return changed;
+		}
+		let lock_timer = match self.lock_timer {
+			Some(timer) => timer + TICK,
+			// Out of resets, so a block landing again locks right away.
+			None if self.settings.lock_mode == LockMode::Extended && self.lock_resets > 0 &&
+				self.lock_resets >= self.settings.lock_resets => self.lock_delay(),
+			None => Duration::ZERO
+		};
+		if lock_timer >= self.lock_delay() {
+			self.lock();
+			return true;
+		}
+		self.lock_timer = Some(lock_timer);
+		changed
+	}
+
+	/// Spawns the next block once the entry delay is over and moves the falling block down
+	/// by sub-cell gravity. At 20G it drops straight onto the stack, even right after spawning.
+	fn master_fall(&mut self) -> bool {
+		let mut changed = false;
+		if self.block.is_none() {
+			self.timer += TICK;
+			if self.timer < self.entry_delay {
+				return false;
+			}
+			// Every block raises the level, except for the last one of a section.
+			if self.level % 100 != 99 && self.level != MASTER_LEVELS - 1 {
+				self.level += 1;
+			}
+			self.spawn();
+			self.fall_units = 0;
+			changed = true;
+		}
+		self.timer = Duration::ZERO;
+		let mut units = master_gravity(self.level);
+		if self.soft_drop {
+			units = units.max(256);
+		}
+		if units >= 20 * 256 {
+			while self.block.is_some() && self.fall() {
+				changed = true;
+			}
+			return changed;
+		}
+		self.fall_units += units;
+		while self.fall_units >= 256 {
+			self.fall_units -= 256;
+			if !self.fall() {
+				self.fall_units = 0;
+				break;
+			}
+			changed = true;
+		}
+		changed
+	}
+
+	/// The entry, line clear and lock delay of the current section in `Mode::Master`.
+	fn master_delays(&self) -> Option<(Duration, Duration, Duration)> {
+		let Mode::Master {are, line_clear_delay} = self.settings.mode else {
+			return None;
+		};
+		let (base_are, base_clear, base_lock) = MASTER_DELAYS[0];
+		let (section_are, section_clear, section_lock) = MASTER_DELAYS[(self.level / 100).min(MASTER_DELAYS.len() - 1)];
+		Some((
+			are * section_are / base_are,
+			line_clear_delay * section_clear / base_clear,
+			self.settings.lock_delay * section_lock / base_lock
+		))
+	}
+
+	fn lock_delay(&self) -> Duration {
+		match self.master_delays() {
+			Some((_, _, lock_delay)) => lock_delay,
+			None => self.settings.lock_delay
+		}
+	}
+
+	fn active_delay(&self) -> Duration {
+		if self.soft_drop && self.block.is_some() {
+			self.delay / self.settings.soft_drop_factor.max(1)
+		} else {
+			self.delay
+		}
+	}
+
+	/// Moves the falling block down one row, or all the way with a soft drop factor of zero.
+	fn soft_fall(&mut self) -> bool {
+		if self.settings.soft_drop_factor != 0 {
+			return self.fall();
+		}
+		let mut moved = false;
+		while self.fall() {
+			moved = true;
+		}
+		moved
+	}
+
+	/// Moves the falling block down a row, or spawns the next one if there is none.
+	fn fall(&mut self) -> bool {
+		if self.state != GameState::Running {
+			return false;
+		}
+		let Some(block) = self.block.as_mut() else {
+			self.spawn();
+			return true;
+		};
+		if !move_block(&self.board, block, 0, 1) {
+			return false;
+		}
+		self.last_kick = None;
+		if block.y_pos > self.lowest_row {
+			self.lowest_row = block.y_pos;
+			self.lock_resets = 0;
+		}
+		true
+	}
+
+	fn lock(&mut self) {
+		if let Some(block) = self.block.take() {
+			let spin = self.spin(&block);
+			let (_, y) = block.position();
+			let bottom = block.tiles().iter().rposition(|row| row.iter().any(|tile| tile.is_some())).unwrap();
+			let lock_out = y + (bottom as isize) < self.board.hidden_rows() as isize;
+			let rows = freeze(&mut self.board, &block);
+			if let Some((are, line_clear_delay, _)) = self.master_delays() {
+				self.entry_delay = if rows > 0 {are + line_clear_delay} else {are};
+			}
+			let perfect_clear = rows > 0 && self.board.is_empty();
+			self.pieces += 1;
+			self.add_score(rows, spin, perfect_clear);
+			if lock_out {
+				self.top_out(Loss::LockOut);
+			}
+			let finished = match (self.goal, self.settings.mode) {
+				(Some(Goal::ClearAll), _) => self.board.is_empty(),
+				(Some(Goal::TSpin {rows : goal}), _) => spin == Spin::Full && rows == goal,
+				(Some(Goal::Lines {lines, ..}), _) => self.cleared_rows >= lines,
+				(None, Mode::Sprint {lines} | Mode::Marathon {lines}) => self.cleared_rows >= lines,
+				(None, Mode::Dig {..}) => self.board.garbage_rows() == 0,
+				(None, Mode::Master {..}) => self.level >= MASTER_LEVELS,
+				_ => false
+			};
+			let failed = matches!(self.goal, Some(Goal::Lines {pieces, ..}) if self.pieces >= pieces);
+			if self.state == GameState::Running {
+				if finished {
+					self.state = GameState::Finished;
+				} else if failed {
+					self.state = GameState::GameOver(Loss::Failed);
+				}
+			}
+		}
+		self.lock_timer = None;
+		self.last_kick = None;
+	}
+
+	/// Classifies a T-spin using the 3-corner rule. It is a full T-spin if both corners the T
+	/// points towards are occupied or if the last rotation used the final kick, otherwise a mini.
+	fn spin(&self, block : &Block) -> Spin {
+		let Some(kick) = self.last_kick else {
+			return Spin::None;
+		};
+		if block.kind() != BlockType::T {
+			return Spin::None;
+		}
+		let corners = corners(&self.board, block);
+		if corners.iter().filter(|&&c| c).count() < 3 {
+			return Spin::None;
+		}
+		if (corners[0] && corners[1]) || kick == 4 {
+			Spin::Full
+		} else {
+			Spin::Mini
+		}
+	}
+
+	fn hold(&mut self) -> bool {
+		if !self.can_hold() {
+			return false;
+		}
+		let block = self.block.take().unwrap();
+		self.timer = Duration::ZERO;
+		match self.held_block.replace(spawn_block(block.kind(), self.settings.rotation, &self.board)) {
+			Some(held) => self.enter(held),
+			None => self.spawn()
+		}
+		self.hold_used = true;
+		true
+	}
+
+	fn spawn(&mut self) {
+		if self.state != GameState::Running {
+			return;
+		}
+		self.hold_used = false;
+		if let Some(kind) = self.queue.pop() {
+			self.enter(spawn_block(kind, self.settings.rotation, &self.board));
+		} else if let Some(held) = self.held_block.take() {
+			// A puzzle that ran out of blocks goes on with the held one.
+			self.enter(held);
+		} else {
+			self.state = GameState::GameOver(Loss::Failed);
+		}
+	}
+
+	/// Makes `block` the falling block, dropping it one row into view if there is room.
+	fn enter(&mut self, mut block : Block) {
+		if self.state != GameState::Running {
+			return;
+		}
+		if overlapps(&self.board, &block) {
+			self.top_out(Loss::BlockOut);
+			if self.state != GameState::Running {
+				return;
+			}
+		}
+		move_block(&self.board, &mut block, 0, 1);
+		self.lowest_row = block.y_pos;
+		self.lock_timer = None;
+		self.lock_resets = 0;
+		self.last_kick = None;
+		self.block = Some(block);
+	}
+
+	/// Ends the game, except in `Mode::Zen` where the stack is cleared and play goes on.
+	fn top_out(&mut self, loss : Loss) {
+		if let Mode::Zen {..} = self.settings.mode {
+			self.board = Board::new(self.settings.width, self.settings.height, self.settings.hidden_rows);
+		} else {
+			self.state = GameState::GameOver(loss);
+		}
+	}
+
+	fn add_score(&mut self, rows : usize, spin : Spin, perfect_clear : bool) {
+		if rows == 0 {
+			self.combo = None;
+			if spin == Spin::None {
+				self.last_action = None;
+				return;
+			}
+		}
+
+		// A mini can only clear up to two rows, anything more needs the T to be wedged in properly.
+		let spin = if spin == Spin::Mini && rows > 2 {Spin::Full} else {spin};
+		let mut points = match (spin, rows) {
+			(Spin::None, 1) => 100,
+			(Spin::None, 2) => 300,
+			(Spin::None, 3) => 500,
+			(Spin::None, 4) => 800,
+			(Spin::Mini, 0) => 100,
+			(Spin::Mini, 1) => 200,
+			(Spin::Mini, 2) => 400,
+			(Spin::Full, 0) => 400,
+			(Spin::Full, 1) => 800,
+			(Spin::Full, 2) => 1200,
+			(Spin::Full, 3) => 1600,
+			_ => unreachable!("Not possible to clear {} rows with {:?}", rows, spin)
+		};
+		let mut back_to_back = false;
+		let mut combo = 0;
+		if rows > 0 {
+			let difficult = rows == 4 || spin != Spin::None;
+			back_to_back = difficult && self.back_to_back;
+			self.back_to_back = difficult;
+			combo = self.combo.map_or(0, |combo| combo + 1);
+			self.combo = Some(combo);
+		}
+		if back_to_back {
+			points = points * 3 / 2;
+		}
+		if perfect_clear {
+			points += match rows {
+				1 => 800,
+				2 => 1200,
+				3 => 1800,
+				4 if back_to_back => 3200,
+				4 => 2000,
+				_ => unreachable!("Not possible to clear {} rows", rows)
+			};
+			self.statistics.perfect_clears += 1;
+		}
+		// Levels in master mode go much higher, so only their section counts.
+		let multiplier = match self.settings.mode {
+			Mode::Master {..} => self.level / 100 + 1,
+			_ => self.level
+		};
+		points = (points + 50 * combo) * multiplier;
+		self.score += points;
+		if rows == 4 {
+			self.statistics.tetrises += 1;
+		}
+		if spin != Spin::None {
+			self.statistics.t_spins += 1;
+		}
+		self.statistics.max_combo = self.statistics.max_combo.max(combo);
+		self.last_action = Some(Action {rows, spin, back_to_back, combo, perfect_clear, points});
+		self.cleared_rows += rows;
+		match self.settings.mode {
+			// Gravity stays at the chosen level.
+			Mode::Ultra {..} | Mode::Zen {..} => return,
+			Mode::Master {..} => {
+				self.level = (self.level + rows).min(MASTER_LEVELS);
+				return;
+			},
+			_ => ()
+		}
+		while LEVEL_LINES.get(self.level - 1).is_some_and(|&lines| self.cleared_rows + self.level_lines >= lines) {
+			self.level += 1;
+			self.delay = gravity(self.level);
+		}
+	}
+}
+
+/// How long a block takes to fall one row at the given level.
+fn gravity(level : usize) -> Duration {
+	let base : f64 = 1.142;
+	let pw = 800.0 * base * base.powi(-(level as i32));
+	Duration::from_millis(pw as u64)
+}
+
+/// Gravity in `Mode::Master` at the given level, in 1/256 rows per tick.
+fn master_gravity(level : usize) -> u32 {
+	MASTER_GRAVITY.iter().rev().find(|&&(start, _)| level >= start).map_or(0, |&(_, units)| units)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::board::Cell;
+
+	#[test]
+	fn hard_drop_spawns_the_next_block_right_away() {
+		let mut game = Game::with_settings(Settings {seed : Some(7), ..Settings::default()});
+		let next = game.next_queue().next().unwrap();
+		game.step(Input::HardDrop);
+		assert_eq!(game.active_piece().map(Block::kind), Some(next));
+		assert_eq!(game.pieces, 1);
+	}
+
+	#[test]
+	fn t_spins_score_by_rows() {
+		let mut game = Game::new();
+		game.add_score(0, Spin::Mini, false);
+		assert_eq!(game.score(), 100);
+		game.add_score(2, Spin::Full, false);
+		assert_eq!(game.score(), 100 + 1200);
+		// A mini that clears three rows counts as a full T-spin.
+		game.add_score(3, Spin::Mini, false);
+		assert_eq!(game.last_action().unwrap().spin, Spin::Full);
+		assert_eq!(game.statistics().t_spins, 3);
+	}
+
+	#[test]
+	fn back_to_back_and_combos() {
+		let mut game = Game::new();
+		game.add_score(4, Spin::None, false);
+		assert_eq!(game.score(), 800);
+		// A second tetris is back to back and continues the combo.
+		game.add_score(4, Spin::None, false);
+		assert_eq!(game.score(), 800 + 1200 + 50);
+		let action = game.last_action().unwrap();
+		assert!(action.back_to_back);
+		assert_eq!(action.combo, 1);
+		// A single keeps the combo going but breaks back to back.
+		game.add_score(1, Spin::None, false);
+		assert_eq!(game.score(), 2050 + 100 + 100);
+		assert!(!game.back_to_back());
+		assert_eq!(game.combo(), Some(2));
+		// A block that clears nothing ends the combo.
+		game.add_score(0, Spin::None, false);
+		assert_eq!(game.combo(), None);
+		assert_eq!(game.statistics().max_combo, 2);
+	}
+
+	#[test]
+	fn perfect_clears_add_a_bonus() {
+		let mut game = Game::new();
+		game.add_score(1, Spin::None, true);
+		assert_eq!(game.score(), 100 + 800);
+		assert!(game.last_action().unwrap().perfect_clear);
+		game.add_score(4, Spin::None, true);
+		game.add_score(4, Spin::None, true);
+		// The second tetris is back to back, which raises its perfect clear bonus.
+		assert_eq!(game.score(), 900 + (800 + 2000 + 50) + (1200 + 3200 + 100));
+		assert_eq!(game.statistics().perfect_clears, 3);
+	}
+
+	/// Drops the falling block onto the floor without locking it and starts its lock delay.
+	fn land(game : &mut Game) {
+		game.step(Input::SoftDrop);
+		game.release(Input::SoftDrop);
+		game.tick();
+		assert!(game.lock_timer.is_some());
+	}
+
+	/// Ticks until the falling block locks, moving it left and right every 20 ticks, and
+	/// returns how many ticks that took.
+	fn ticks_until_lock(game : &mut Game, limit : usize) -> Option<usize> {
+		for tick in 1..=limit {
+			if tick % 20 == 0 {
+				let input = if tick % 40 == 0 {Input::MoveLeft} else {Input::MoveRight};
+				game.step(input);
+				game.release(input);
+			}
+			game.tick();
+			if game.pieces > 0 {
+				return Some(tick);
+			}
+		}
+		None
+	}
+
+	fn lock_settings(lock_mode : LockMode) -> Settings {
+		Settings {lock_mode, lock_resets : 2, soft_drop_factor : 0, seed : Some(3), ..Settings::default()}
+	}
+
+	#[test]
+	fn extended_lock_delay_resets_a_limited_number_of_times() {
+		let mut game = Game::with_settings(lock_settings(LockMode::Extended));
+		land(&mut game);
+		// Resets at 20 and 40 ticks, none at 60, so the delay of 30 ticks runs out during tick 69.
+		assert_eq!(ticks_until_lock(&mut game, 200), Some(69));
+		assert_eq!(game.pieces, 1);
+	}
+
+	#[test]
+	fn infinite_lock_delay_resets_on_every_move() {
+		let mut game = Game::with_settings(lock_settings(LockMode::Infinite));
+		land(&mut game);
+		assert_eq!(ticks_until_lock(&mut game, 200), None);
+	}
+
+	#[test]
+	fn classic_lock_delay_is_not_reset_by_moves() {
+		let mut game = Game::with_settings(lock_settings(LockMode::Classic));
+		land(&mut game);
+		assert_eq!(ticks_until_lock(&mut game, 200), Some(30));
+	}
+
+	#[test]
+	fn reaching_a_new_lowest_row_restores_the_resets() {
+		let mut game = Game::with_settings(lock_settings(LockMode::Extended));
+		game.lock_resets = 2;
+		game.step(Input::SoftDrop);
+		game.release(Input::SoftDrop);
+		assert_eq!(game.lock_resets, 0);
+		// Without resets left the block would lock as soon as it landed.
+		game.tick();
+		assert_eq!(game.pieces, 0);
+		assert_eq!(game.lock_timer, Some(Duration::ZERO));
+	}
+
+	fn shift_settings(arr : Duration) -> Settings {
+		Settings {das : TICK * 10, arr, seed : Some(5), ..Settings::default()}
+	}
+
+	fn x_pos(game : &Game) -> isize {
+		game.block.as_ref().unwrap().position().0
+	}
+
+	#[test]
+	fn auto_shift_waits_for_das_then_repeats_every_arr() {
+		let mut game = Game::with_settings(shift_settings(TICK * 2));
+		let start = x_pos(&game);
+		game.step(Input::MoveLeft);
+		let mut positions = vec![];
+		for _ in 0..13 {
+			game.tick();
+			positions.push(start - x_pos(&game));
+		}
+		assert_eq!(positions, [1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 3, 3]);
+	}
+
+	#[test]
+	fn zero_arr_shifts_to_the_wall_at_once() {
+		let mut game = Game::with_settings(shift_settings(Duration::ZERO));
+		game.step(Input::MoveRight);
+		for _ in 0..9 {
+			game.tick();
+		}
+		let mut block = game.block.clone().unwrap();
+		assert!(move_block(&game.board, &mut block, 1, 0));
+		game.tick();
+		let mut block = game.block.clone().unwrap();
+		assert!(!move_block(&game.board, &mut block, 1, 0));
+	}
+
+	#[test]
+	fn block_out_at_20g_ends_the_game() {
+		let mode = Mode::Master {are : Duration::ZERO, line_clear_delay : Duration::ZERO};
+		let mut game = Game::with_settings(Settings {mode, seed : Some(1), ..Settings::default()});
+		game.level = 500;
+		game.step(Input::HardDrop);
+		// Stack up to where the next block spawns.
+		let top = game.board.hidden_rows() - 2;
+		for y in top..top + 2 {
+			for x in 0..game.board.width() - 1 {
+				game.board[y][x] = Some(Cell::Garbage);
+			}
+		}
+		game.tick();
+		assert_eq!(game.state(), GameState::GameOver(Loss::BlockOut));
+		assert!(game.active_piece().is_none());
+		// Nothing spawns after the game is over.
+		game.tick();
+		assert!(game.active_piece().is_none());
+	}
+
+	#[test]
+	fn garbage_lifts_the_falling_block() {
+		let mut game = Game::with_settings(Settings {soft_drop_factor : 0, seed : Some(2), ..Settings::default()});
+		game.step(Input::SoftDrop);
+		let y = game.block.as_ref().unwrap().position().1;
+		game.add_garbage(&[0, 4]);
+		assert_eq!(game.state(), GameState::Running);
+		assert_eq!(game.block.as_ref().unwrap().position().1, y - 2);
+		assert_eq!(game.board.garbage_rows(), 2);
+		let height = game.board.height();
+		assert_eq!(game.board[height - 1][4], None);
+		assert_eq!(game.board[height - 2][0], None);
+	}
+
+	#[test]
+	fn garbage_pushing_the_stack_out_tops_out() {
+		let mut game = Game::with_settings(Settings {seed : Some(2), ..Settings::default()});
+		game.board[0][0] = Some(Cell::Garbage);
+		game.add_garbage(&[0]);
+		assert_eq!(game.state(), GameState::GameOver(Loss::TopOut));
+
+		// Zen clears the board instead.
+		let mut game = Game::with_settings(Settings {mode : Mode::Zen {level : 1}, seed : Some(2), ..Settings::default()});
+		game.board[0][0] = Some(Cell::Garbage);
+		game.add_garbage(&[0]);
+		assert_eq!(game.state(), GameState::Running);
+		assert_eq!(game.board.garbage_rows(), 0);
+	}
+}
@@ -0,0 +1,23 @@
+//! The rules of Tetris, independent of how the game is drawn or controlled.
+//!
+//! A [`Game`] is advanced by calling [`Game::tick`] once every [`TICK`] and fed player
+//! actions through [`Game::step`]. Everything needed to draw the game can be read back
+//! through its accessors.
+
+mod block;
+mod board;
+mod game;
+mod puzzle;
+mod queue;
+mod randomizer;
+mod replay;
+mod rotation;
+
+pub use block::{Block, BlockType, create_block};
+pub use board::{Board, DEFAULT_WIDTH, DEFAULT_HEIGHT, DEFAULT_HIDDEN_ROWS, Cell};
+pub use game::{Action, Game, GameState, Input, LockMode, Loss, Mode, Settings, Spin, Statistics, TICK};
+pub use puzzle::{Goal, Puzzle};
+pub use queue::{PieceQueue, QUEUE_LENGTH};
+pub use randomizer::Randomizer;
+pub use replay::{Event, Playback, Replay};
+pub use rotation::RotationSystem;
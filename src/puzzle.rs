@@ -0,0 +1,154 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::block::BlockType;
+use crate::board::{Board, DEFAULT_HEIGHT, DEFAULT_HIDDEN_ROWS};
+
+/// What has to be done to solve a puzzle.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Goal {
+	/// Leave the board completely empty.
+	ClearAll,
+	/// Clear exactly `rows` lines with a single full T-spin.
+	TSpin {rows : usize},
+	/// Clear `lines` lines with no more than `pieces` blocks.
+	Lines {lines : usize, pieces : usize}
+}
+
+impl fmt::Display for Goal {
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Goal::ClearAll => write!(f, "Clear all"),
+			Goal::TSpin {rows : 1} => write!(f, "T-spin single"),
+			Goal::TSpin {rows : 2} => write!(f, "T-spin double"),
+			Goal::TSpin {rows : 3} => write!(f, "T-spin triple"),
+			Goal::TSpin {rows} => write!(f, "T-spin of {} lines", rows),
+			Goal::Lines {lines, pieces} => write!(f, "{} lines in {} pieces", lines, pieces)
+		}
+	}
+}
+
+/// A fixed starting board, the blocks to play in order and the goal to reach with them.
+///
+/// Puzzles are stored as text. Empty lines and lines starting with `#` are ignored, the others
+/// are `goal clear-all`, `goal t-spin <rows>` or `goal lines <lines> <pieces>`, then
+/// `pieces` followed by the block letters, and finally `board` followed by the rows of the
+/// board in the form read by `Board::parse` until the end of the file.
+#[derive(Debug, Clone)]
+pub struct Puzzle {
+	pub(crate) board : Board,
+	pub(crate) pieces : Vec<BlockType>,
+	pub(crate) goal : Goal
+}
+
+impl Puzzle {
+	pub fn board(&self) -> &Board {
+		&self.board
+	}
+
+	/// The blocks that are dealt, in order.
+	pub fn pieces(&self) -> &[BlockType] {
+		&self.pieces
+	}
+
+	pub fn goal(&self) -> Goal {
+		self.goal
+	}
+
+	pub fn load<P : AsRef<Path>>(path : P) -> io::Result<Puzzle> {
+		let text = fs::read_to_string(path)?;
+		Puzzle::parse(&text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+	}
+
+	pub fn parse(text : &str) -> Result<Puzzle, String> {
+		let mut goal = None;
+		let mut pieces = vec![];
+		let mut lines = text.lines().enumerate();
+		while let Some((number, line)) = lines.next() {
+			let words : Vec<&str> = line.split_whitespace().collect();
+			let invalid = || format!("Invalid puzzle line {} : {}", number + 1, line);
+			match words[..] {
+				[] => continue,
+				[comment, ..] if comment.starts_with('#') => continue,
+				["goal", "clear-all"] => goal = Some(Goal::ClearAll),
+				["goal", "t-spin", rows] => goal = Some(Goal::TSpin {
+					rows : rows.parse().ok().filter(|rows| (1..=3).contains(rows)).ok_or_else(invalid)?
+				}),
+				["goal", "lines", lines, pieces] => goal = Some(Goal::Lines {
+					lines : lines.parse().ok().filter(|&lines| lines > 0).ok_or_else(invalid)?,
+					pieces : pieces.parse().ok().filter(|&pieces| pieces > 0).ok_or_else(invalid)?
+				}),
+				["pieces", letters] => pieces = letters.chars().map(BlockType::from_letter).collect::<Option<_>>().ok_or_else(invalid)?,
+				["board"] => {
+					let rows : Vec<&str> = lines.map(|(_, line)| line).collect();
+					let board = Board::parse(&rows.join("\n"), DEFAULT_HEIGHT, DEFAULT_HIDDEN_ROWS)?;
+					if pieces.is_empty() {
+						return Err(String::from("A puzzle needs at least one piece"));
+					}
+					let goal = goal.ok_or("A puzzle needs a goal")?;
+					return Ok(Puzzle {board, pieces, goal});
+				},
+				_ => return Err(invalid())
+			}
+		}
+		Err(String::from("A puzzle needs a board"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+	use crate::game::{Game, GameState, Input, Loss, Settings};
+
+	/// Plays the inputs on a shipped puzzle, with instant soft drops and no auto shift.
+	fn solve(file : &str, inputs : &[Input]) -> Game {
+		let puzzle = Puzzle::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("puzzles").join(file)).unwrap();
+		let settings = Settings {soft_drop_factor : 0, das : Duration::from_secs(10), ..Settings::default()};
+		let mut game = Game::with_puzzle(settings, &puzzle);
+		for &input in inputs {
+			game.step(input);
+			game.release(input);
+		}
+		game
+	}
+
+	#[test]
+	fn clear_all_is_solvable() {
+		let game = solve("01-clear-all.txt", &[Input::HardDrop, Input::MoveLeft, Input::HardDrop]);
+		assert_eq!(game.state(), GameState::Finished);
+		assert!(game.board().is_empty());
+	}
+
+	#[test]
+	fn t_spin_double_is_solvable() {
+		let game = solve("02-t-spin-double.txt", &[
+			Input::MoveLeft, Input::RotateClockwise, Input::SoftDrop, Input::RotateClockwise, Input::HardDrop
+		]);
+		assert_eq!(game.state(), GameState::Finished);
+		assert_eq!(game.lines(), 2);
+	}
+
+	#[test]
+	fn lines_is_solvable() {
+		let game = solve("03-lines.txt", &[
+			Input::RotateClockwise, Input::MoveLeft, Input::MoveLeft, Input::MoveLeft, Input::MoveLeft, Input::HardDrop,
+			Input::RotateClockwise, Input::MoveRight, Input::MoveRight, Input::MoveRight, Input::HardDrop
+		]);
+		assert_eq!(game.state(), GameState::Finished);
+		assert_eq!(game.lines(), 4);
+	}
+
+	#[test]
+	fn running_out_of_pieces_fails() {
+		let game = solve("01-clear-all.txt", &[Input::HardDrop, Input::HardDrop]);
+		assert_eq!(game.state(), GameState::GameOver(Loss::Failed));
+	}
+
+	#[test]
+	fn parse_needs_a_goal_and_pieces() {
+		assert!(Puzzle::parse("pieces T\nboard\n....\n").is_err());
+		assert!(Puzzle::parse("goal clear-all\nboard\n....\n").is_err());
+		assert!(Puzzle::parse("goal lines 0 3\npieces T\nboard\n....\n").is_err());
+	}
+}
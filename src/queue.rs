@@ -0,0 +1,58 @@
+use std::collections::VecDeque;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::block::BlockType;
+use crate::randomizer::{Generator, Randomizer};
+
+/// The minimum number of upcoming blocks that can always be peeked.
+pub const QUEUE_LENGTH : usize = 7;
+
+/// The upcoming blocks, refilled from a randomizer whenever it runs low, or a fixed sequence
+/// that runs out.
+pub struct PieceQueue {
+	pieces : VecDeque<BlockType>,
+	generator : Option<Box<dyn Generator>>,
+	rng : StdRng
+}
+
+impl Default for PieceQueue {
+	fn default() -> Self {
+		Self::new(Randomizer::Bag7, rand::random())
+	}
+}
+
+impl PieceQueue {
+	/// Creates a queue whose blocks are fully determined by the randomizer and seed.
+	pub fn new(randomizer : Randomizer, seed : u64) -> PieceQueue {
+		let mut queue = PieceQueue {pieces : VecDeque::new(), generator : Some(randomizer.generator()), rng : StdRng::seed_from_u64(seed)};
+		queue.refill();
+		queue
+	}
+
+	/// Creates a queue that deals exactly the given blocks.
+	pub fn fixed(pieces : &[BlockType]) -> PieceQueue {
+		PieceQueue {pieces : pieces.iter().copied().collect(), generator : None, rng : StdRng::seed_from_u64(0)}
+	}
+
+	/// Removes and returns the next block type, or `None` once a fixed sequence has run out.
+	pub fn pop(&mut self) -> Option<BlockType> {
+		let piece = self.pieces.pop_front();
+		self.refill();
+		piece
+	}
+
+	/// The upcoming block types in order. At least `QUEUE_LENGTH` are available, unless the
+	/// queue is a fixed sequence.
+	pub fn peek(&self) -> impl Iterator<Item = BlockType> + '_ {
+		self.pieces.iter().copied()
+	}
+
+	fn refill(&mut self) {
+		let Some(generator) = self.generator.as_mut() else {
+			return;
+		};
+		while self.pieces.len() < QUEUE_LENGTH {
+			self.pieces.push_back(generator.next(&mut self.rng));
+		}
+	}
+}
@@ -0,0 +1,106 @@
+use rand::{seq::SliceRandom, Rng, RngCore};
+
+use crate::block::BlockType;
+
+const BLOCKS : [BlockType; 7] = [BlockType::I, BlockType::J, BlockType::L, BlockType::O, BlockType::S, BlockType::T, BlockType::Z];
+
+/// The algorithms that can be used to pick the upcoming blocks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Randomizer {
+	/// Deals shuffled bags containing each block once.
+	Bag7,
+	/// Deals shuffled bags containing each block twice.
+	Bag14,
+	/// Picks every block independently.
+	Random,
+	/// Rerolls once when the same block comes twice in a row, as on the NES.
+	Nes,
+	/// Rolls up to `retries` times until it gets a block that is not among the last four, as in TGM.
+	/// TGM uses 4 retries and TGM2 uses 6.
+	History {retries : usize}
+}
+
+impl Randomizer {
+	pub(crate) fn generator(&self) -> Box<dyn Generator> {
+		match *self {
+			Randomizer::Bag7 => Box::new(Bag {copies : 1, pieces : vec![]}),
+			Randomizer::Bag14 => Box::new(Bag {copies : 2, pieces : vec![]}),
+			Randomizer::Random => Box::new(Random),
+			Randomizer::Nes => Box::new(Nes {last : None}),
+			Randomizer::History {retries} => Box::new(History {retries, history : None})
+		}
+	}
+}
+
+/// Produces the sequence of blocks for a `Randomizer`.
+pub(crate) trait Generator {
+	fn next(&mut self, rng : &mut dyn RngCore) -> BlockType;
+}
+
+struct Bag {
+	copies : usize,
+	pieces : Vec<BlockType>
+}
+
+impl Generator for Bag {
+	fn next(&mut self, rng : &mut dyn RngCore) -> BlockType {
+		if self.pieces.is_empty() {
+			for _ in 0..self.copies {
+				self.pieces.extend(BLOCKS);
+			}
+			self.pieces.shuffle(rng);
+		}
+		self.pieces.pop().unwrap()
+	}
+}
+
+struct Random;
+
+impl Generator for Random {
+	fn next(&mut self, rng : &mut dyn RngCore) -> BlockType {
+		*BLOCKS.choose(rng).unwrap()
+	}
+}
+
+struct Nes {
+	last : Option<BlockType>
+}
+
+impl Generator for Nes {
+	fn next(&mut self, rng : &mut dyn RngCore) -> BlockType {
+		// The eighth outcome is a dummy that always causes a reroll.
+		let roll = rng.gen_range(0..BLOCKS.len() + 1);
+		let piece = match BLOCKS.get(roll) {
+			Some(&piece) if Some(piece) != self.last => piece,
+			_ => *BLOCKS.choose(rng).unwrap()
+		};
+		self.last = Some(piece);
+		piece
+	}
+}
+
+struct History {
+	retries : usize,
+	history : Option<[BlockType; 4]>
+}
+
+impl Generator for History {
+	fn next(&mut self, rng : &mut dyn RngCore) -> BlockType {
+		let Some(history) = self.history.as_mut() else {
+			// The first block is never one that can not be placed flat on an empty board.
+			let piece = *[BlockType::I, BlockType::J, BlockType::L, BlockType::T].choose(rng).unwrap();
+			self.history = Some([piece, BlockType::Z, BlockType::Z, BlockType::Z]);
+			return piece;
+		};
+		let mut piece = *BLOCKS.choose(rng).unwrap();
+		for _ in 1..self.retries {
+			if !history.contains(&piece) {
+				break;
+			}
+			piece = *BLOCKS.choose(rng).unwrap();
+		}
+		history.rotate_right(1);
+		history[0] = piece;
+		piece
+	}
+}
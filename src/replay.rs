@@ -0,0 +1,298 @@
+use std::{fs, io, path::Path, time::Duration};
+
+use crate::game::{Game, GameState, Input, LockMode, Mode, Settings};
+use crate::randomizer::Randomizer;
+use crate::rotation::RotationSystem;
+
+const HEADER : &str = "tetris replay 1";
+
+/// A key press or release, as passed to `Game::step` and `Game::release`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+	Press(Input),
+	Release(Input)
+}
+
+/// Everything needed to play a game again: its settings including the seed, and every
+/// input with the number of ticks that had passed when it happened.
+#[derive(Debug, Clone)]
+pub struct Replay {
+	pub(crate) settings : Settings,
+	pub(crate) events : Vec<(u64, Event)>,
+	pub(crate) length : u64
+}
+
+impl Replay {
+	pub fn settings(&self) -> &Settings {
+		&self.settings
+	}
+
+	/// The recorded inputs in order, each with the tick it happened on.
+	pub fn events(&self) -> &[(u64, Event)] {
+		&self.events
+	}
+
+	/// How many ticks the game lasted.
+	pub fn length(&self) -> u64 {
+		self.length
+	}
+
+	pub fn save<P : AsRef<Path>>(&self, path : P) -> io::Result<()> {
+		let s = &self.settings;
+		let mut lines = vec![
+			String::from(HEADER),
+			format!("width {}", s.width),
+			format!("height {}", s.height),
+			format!("hidden_rows {}", s.hidden_rows),
+			format!("hold {}", s.hold),
+			format!("lock_mode {:?}", s.lock_mode),
+			format!("lock_delay {}", s.lock_delay.as_micros()),
+			format!("lock_resets {}", s.lock_resets),
+			format!("das {}", s.das.as_micros()),
+			format!("arr {}", s.arr.as_micros()),
+			format!("soft_drop_factor {}", s.soft_drop_factor),
+			match s.randomizer {
+				Randomizer::History {retries} => format!("randomizer History {}", retries),
+				randomizer => format!("randomizer {:?}", randomizer)
+			},
+			format!("rotation {:?}", s.rotation),
+			format!("seed {}", s.seed.unwrap_or_default()),
+			match s.mode {
+				Mode::Endless => String::from("mode Endless"),
+				Mode::Marathon {lines} => format!("mode Marathon {}", lines),
+				Mode::Sprint {lines} => format!("mode Sprint {}", lines),
+				Mode::Ultra {time, level} => format!("mode Ultra {} {}", time.as_micros(), level),
+				Mode::Zen {level} => format!("mode Zen {}", level),
+				Mode::Dig {rows, messiness} => format!("mode Dig {} {}", rows, messiness),
+				Mode::Master {are, line_clear_delay} => format!("mode Master {} {}", are.as_micros(), line_clear_delay.as_micros()),
+				Mode::Survival {interval} => format!("mode Survival {}", interval.as_micros())
+			},
+			format!("start_level {}", s.start_level),
+			format!("length {}", self.length)
+		];
+		for (tick, event) in &self.events {
+			lines.push(match event {
+				Event::Press(input) => format!("press {} {:?}", tick, input),
+				Event::Release(input) => format!("release {} {:?}", tick, input)
+			});
+		}
+		lines.push(String::new());
+		fs::write(path, lines.join("\n"))
+	}
+
+	pub fn load<P : AsRef<Path>>(path : P) -> io::Result<Replay> {
+		let text = fs::read_to_string(path)?;
+		let mut lines = text.lines().enumerate();
+		if lines.next().map(|(_, line)| line) != Some(HEADER) {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a replay file"));
+		}
+		let mut replay = Replay {settings : Settings {seed : Some(0), ..Settings::default()}, events : vec![], length : 0};
+		for (number, line) in lines {
+			if line.trim().is_empty() {
+				continue;
+			}
+			if parse_line(&mut replay, line).is_none() {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid replay line {} : {}", number + 1, line)));
+			}
+		}
+		Ok(replay)
+	}
+}
+
+fn parse_line(replay : &mut Replay, line : &str) -> Option<()> {
+	let words : Vec<&str> = line.split_whitespace().collect();
+	let s = &mut replay.settings;
+	match words[..] {
+		["width", value] => s.width = value.parse().ok().filter(|&width| width >= 4)?,
+		["height", value] => s.height = value.parse().ok().filter(|&height| height >= 4)?,
+		["hidden_rows", value] => s.hidden_rows = value.parse().ok()?,
+		["hold", value] => s.hold = value.parse().ok()?,
+		["lock_mode", value] => s.lock_mode = match value {
+			"Extended" => LockMode::Extended,
+			"Infinite" => LockMode::Infinite,
+			"Classic" => LockMode::Classic,
+			_ => return None
+		},
+		["lock_delay", value] => s.lock_delay = Duration::from_micros(value.parse().ok()?),
+		["lock_resets", value] => s.lock_resets = value.parse().ok()?,
+		["das", value] => s.das = Duration::from_micros(value.parse().ok()?),
+		["arr", value] => s.arr = Duration::from_micros(value.parse().ok()?),
+		["soft_drop_factor", value] => s.soft_drop_factor = value.parse().ok()?,
+		["randomizer", "History", retries] => s.randomizer = Randomizer::History {retries : retries.parse().ok()?},
+		["randomizer", value] => s.randomizer = match value {
+			"Bag7" => Randomizer::Bag7,
+			"Bag14" => Randomizer::Bag14,
+			"Random" => Randomizer::Random,
+			"Nes" => Randomizer::Nes,
+			_ => return None
+		},
+		["rotation", value] => s.rotation = match value {
+			"Srs" => RotationSystem::Srs,
+			"Ars" => RotationSystem::Ars,
+			"Nrs" => RotationSystem::Nrs,
+			"Sega" => RotationSystem::Sega,
+			_ => return None
+		},
+		["mode", "Endless"] => s.mode = Mode::Endless,
+		["mode", "Marathon", lines] => s.mode = Mode::Marathon {lines : lines.parse().ok()?},
+		["mode", "Sprint", lines] => s.mode = Mode::Sprint {lines : lines.parse().ok()?},
+		["mode", "Dig", rows, messiness] => s.mode = Mode::Dig {rows : rows.parse().ok()?, messiness : messiness.parse().ok()?},
+		["mode", "Master", are, line_clear_delay] => s.mode = Mode::Master {
+			are : Duration::from_micros(are.parse().ok()?),
+			line_clear_delay : Duration::from_micros(line_clear_delay.parse().ok()?)
+		},
+		["mode", "Survival", interval] => s.mode = Mode::Survival {interval : Duration::from_micros(interval.parse().ok()?)},
+		["mode", "Zen", level] => s.mode = Mode::Zen {level : level.parse().ok()?},
+		["mode", "Ultra", time, level] => s.mode = Mode::Ultra {time : Duration::from_micros(time.parse().ok()?), level : level.parse().ok()?},
+		["seed", value] => s.seed = Some(value.parse().ok()?),
+		["start_level", value] => s.start_level = value.parse().ok()?,
+		["length", value] => replay.length = value.parse().ok()?,
+		[kind, tick, input] => {
+			let tick : u64 = tick.parse().ok()?;
+			// Events have to be in order and within the game for playback to reach them.
+			if tick > replay.length || replay.events.last().is_some_and(|&(last, _)| last > tick) {
+				return None;
+			}
+			let input = parse_input(input)?;
+			let event = match kind {
+				"press" => Event::Press(input),
+				"release" => Event::Release(input),
+				_ => return None
+			};
+			replay.events.push((tick, event));
+		},
+		_ => return None
+	}
+	Some(())
+}
+
+fn parse_input(name : &str) -> Option<Input> {
+	Some(match name {
+		"MoveLeft" => Input::MoveLeft,
+		"MoveRight" => Input::MoveRight,
+		"RotateClockwise" => Input::RotateClockwise,
+		"RotateCounterClockwise" => Input::RotateCounterClockwise,
+		"SoftDrop" => Input::SoftDrop,
+		"HardDrop" => Input::HardDrop,
+		"Hold" => Input::Hold,
+		_ => return None
+	})
+}
+
+/// Plays a replay back through a fresh game, one tick at a time.
+pub struct Playback {
+	replay : Replay,
+	game : Game,
+	next : usize
+}
+
+impl Playback {
+	pub fn new(replay : Replay) -> Playback {
+		let game = Game::with_settings(replay.settings.clone());
+		Playback {replay, game, next : 0}
+	}
+
+	pub fn game(&self) -> &Game {
+		&self.game
+	}
+
+	pub fn replay(&self) -> &Replay {
+		&self.replay
+	}
+
+	/// Whether every recorded input has been applied and the game has run for as long as it did.
+	pub fn is_finished(&self) -> bool {
+		self.game.state() != GameState::Running ||
+			(self.game.ticks() >= self.replay.length && self.next >= self.replay.events.len())
+	}
+
+	/// Applies the inputs of the current tick and advances the game by one tick, returning
+	/// `true` if the board or falling block changed.
+	pub fn advance(&mut self) -> bool {
+		let mut changed = self.apply_events();
+		if self.game.ticks() < self.replay.length {
+			changed |= self.game.tick();
+		}
+		changed
+	}
+
+	/// Applies the inputs that happened before the next tick.
+	fn apply_events(&mut self) -> bool {
+		let mut changed = false;
+		while let Some(&(tick, event)) = self.replay.events.get(self.next) {
+			if tick > self.game.ticks() {
+				break;
+			}
+			changed |= match event {
+				Event::Press(input) => self.game.step(input),
+				Event::Release(input) => {
+					self.game.release(input);
+					false
+				}
+			};
+			self.next += 1;
+		}
+		changed
+	}
+
+	/// Jumps to the given tick, replaying the game from the start when going backwards.
+	pub fn seek(&mut self, tick : u64) {
+		if tick < self.game.ticks() {
+			self.game = Game::with_settings(self.replay.settings.clone());
+			self.next = 0;
+		}
+		while self.game.ticks() < tick && !self.is_finished() {
+			self.advance();
+		}
+		self.apply_events();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn saved_replay_plays_back_the_same_game() {
+		let settings = Settings {seed : Some(42), mode : Mode::Sprint {lines : 40}, ..Settings::default()};
+		let mut game = Game::with_settings(settings);
+		let inputs = [Input::MoveLeft, Input::RotateClockwise, Input::HardDrop, Input::MoveRight, Input::SoftDrop, Input::Hold];
+		for i in 0..3000 {
+			if game.state() != GameState::Running {
+				break;
+			}
+			if i % 7 == 0 {
+				let input = inputs[i / 7 % inputs.len()];
+				game.step(input);
+				game.release(input);
+			}
+			game.tick();
+		}
+		let path = std::env::temp_dir().join(format!("tetris-test-{}.replay", std::process::id()));
+		game.replay().save(&path).unwrap();
+		let replay = Replay::load(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(replay.settings().mode, Mode::Sprint {lines : 40});
+		assert_eq!(replay.events(), game.replay().events());
+
+		let mut playback = Playback::new(replay);
+		while !playback.is_finished() {
+			playback.advance();
+		}
+		assert_eq!(playback.game().board(), game.board());
+		assert_eq!(playback.game().score(), game.score());
+		// Seeking back restarts the game and arrives at the same place.
+		playback.seek(10);
+		playback.seek(game.ticks());
+		assert_eq!(playback.game().board(), game.board());
+	}
+
+	#[test]
+	fn load_rejects_other_files() {
+		let path = std::env::temp_dir().join(format!("tetris-test-{}.txt", std::process::id()));
+		fs::write(&path, "not a replay\n").unwrap();
+		let error = Replay::load(&path).unwrap_err();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+	}
+}
@@ -0,0 +1,326 @@
+use crate::block::{Block, BlockType};
+use crate::board::Board;
+
+/// The rules for how blocks spawn and turn.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RotationSystem {
+	/// The Super Rotation System of modern guideline games, with five kicks per rotation.
+	Srs,
+	/// The Arika Rotation System of TGM. Blocks spawn flat side up and kick one cell right or
+	/// left, except the I, and except a J, L or T blocked in the center column.
+	Ars,
+	/// The Nintendo Rotation System of NES Tetris. No kicks, and the I, S and Z only have two
+	/// orientations which lean to the right.
+	Nrs,
+	/// The rotation of Sega Tetris, which ARS grew out of, without any kicks.
+	Sega
+}
+
+impl RotationSystem {
+	pub(crate) fn rules(&self) -> &'static dyn Rules {
+		match self {
+			RotationSystem::Srs => &Srs,
+			RotationSystem::Ars => &Ars,
+			RotationSystem::Nrs => &Nrs,
+			RotationSystem::Sega => &Sega
+		}
+	}
+}
+
+/// The orientations and kicks of a `RotationSystem`.
+pub(crate) trait Rules {
+	/// The four rotation states of a block in clockwise order, the first being the one it spawns in.
+	fn states(&self, name : BlockType) -> [Vec<Vec<bool>>; 4];
+
+	/// The offsets tried in order after `block` turned into its current rotation state, until one fits.
+	fn kicks(&self, board : &Board, block : &Block, clockwise : bool) -> &'static [(isize, isize)];
+}
+
+const NO_KICKS : &[(isize, isize)] = &[(0, 0)];
+
+struct Srs;
+
+impl Rules for Srs {
+	fn states(&self, name : BlockType) -> [Vec<Vec<bool>>; 4] {
+		match name {
+			BlockType::I => [
+				vec![vec![false, false, false, false], vec![true, true, true, true],
+					vec![false, false, false, false], vec![false, false, false, false]],
+				vec![vec![false, false, true, false], vec![false, false, true, false],
+					vec![false, false, true, false], vec![false, false, true, false]],
+				vec![vec![false, false, false, false], vec![false, false, false, false],
+					vec![true, true, true, true], vec![false, false, false, false]],
+				vec![vec![false, true, false, false], vec![false, true, false, false],
+					vec![false, true, false, false], vec![false, true, false, false]]
+			],
+			BlockType::J => [
+				vec![vec![true, false, false], vec![true, true, true], vec![false, false, false]],
+				vec![vec![false, true, true], vec![false, true, false], vec![false, true, false]],
+				vec![vec![false, false, false], vec![true, true, true], vec![false, false, true]],
+				vec![vec![false, true, false], vec![false, true, false], vec![true, true, false]]
+			],
+			BlockType::L => [
+				vec![vec![false, false, true], vec![true, true, true], vec![false, false, false]],
+				vec![vec![false, true, false], vec![false, true, false], vec![false, true, true]],
+				vec![vec![false, false, false], vec![true, true, true], vec![true, false, false]],
+				vec![vec![true, true, false], vec![false, true, false], vec![false, true, false]]
+			],
+			BlockType::O => o_states(),
+			BlockType::S => [
+				vec![vec![false, true, true], vec![true, true, false], vec![false, false, false]],
+				vec![vec![false, true, false], vec![false, true, true], vec![false, false, true]],
+				vec![vec![false, false, false], vec![false, true, true], vec![true, true, false]],
+				vec![vec![true, false, false], vec![true, true, false], vec![false, true, false]]
+			],
+			BlockType::T => [
+				vec![vec![false, true, false], vec![true, true, true], vec![false, false, false]],
+				vec![vec![false, true, false], vec![false, true, true], vec![false, true, false]],
+				vec![vec![false, false, false], vec![true, true, true], vec![false, true, false]],
+				vec![vec![false, true, false], vec![true, true, false], vec![false, true, false]]
+			],
+			BlockType::Z => [
+				vec![vec![true, true, false], vec![false, true, true], vec![false, false, false]],
+				vec![vec![false, false, true], vec![false, true, true], vec![false, true, false]],
+				vec![vec![false, false, false], vec![true, true, false], vec![false, true, true]],
+				vec![vec![false, true, false], vec![true, true, false], vec![true, false, false]]
+			]
+		}
+	}
+
+	fn kicks(&self, _board : &Board, block : &Block, clockwise : bool) -> &'static [(isize, isize)] {
+		match block.kind() {
+			BlockType::O => NO_KICKS,
+			BlockType::I => match (clockwise, block.rotation()) {
+				(true, 1) | (false, 2) => &[(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+				(true, 2) | (false, 3) => &[(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+				(true, 3) | (false, 0) => &[(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+				(true, 0) | (false, 1) => &[(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+				_ => unreachable!()
+			},
+			_ => match (clockwise, block.rotation()) {
+				(true, 1) | (false, 1) => &[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+				(true, 2) | (false, 0) => &[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+				(true, 3) | (false, 3) => &[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+				(true, 0) | (false, 2) => &[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+				_ => unreachable!()
+			}
+		}
+	}
+}
+
+struct Ars;
+
+impl Rules for Ars {
+	fn states(&self, name : BlockType) -> [Vec<Vec<bool>>; 4] {
+		match name {
+			BlockType::I => [
+				vec![vec![false, false, false, false], vec![true, true, true, true],
+					vec![false, false, false, false], vec![false, false, false, false]],
+				vec![vec![false, false, true, false], vec![false, false, true, false],
+					vec![false, false, true, false], vec![false, false, true, false]],
+				vec![vec![false, false, false, false], vec![true, true, true, true],
+					vec![false, false, false, false], vec![false, false, false, false]],
+				vec![vec![false, false, true, false], vec![false, false, true, false],
+					vec![false, false, true, false], vec![false, false, true, false]]
+			],
+			BlockType::J => [
+				vec![vec![false, false, false], vec![true, true, true], vec![false, false, true]],
+				vec![vec![false, true, false], vec![false, true, false], vec![true, true, false]],
+				vec![vec![false, false, false], vec![true, false, false], vec![true, true, true]],
+				vec![vec![false, true, true], vec![false, true, false], vec![false, true, false]]
+			],
+			BlockType::L => [
+				vec![vec![false, false, false], vec![true, true, true], vec![true, false, false]],
+				vec![vec![true, true, false], vec![false, true, false], vec![false, true, false]],
+				vec![vec![false, false, false], vec![false, false, true], vec![true, true, true]],
+				vec![vec![false, true, false], vec![false, true, false], vec![false, true, true]]
+			],
+			BlockType::O => o_states(),
+			BlockType::S => [
+				vec![vec![false, false, false], vec![false, true, true], vec![true, true, false]],
+				vec![vec![true, false, false], vec![true, true, false], vec![false, true, false]],
+				vec![vec![false, false, false], vec![false, true, true], vec![true, true, false]],
+				vec![vec![true, false, false], vec![true, true, false], vec![false, true, false]]
+			],
+			BlockType::T => [
+				vec![vec![false, false, false], vec![true, true, true], vec![false, true, false]],
+				vec![vec![false, true, false], vec![true, true, false], vec![false, true, false]],
+				vec![vec![false, false, false], vec![false, true, false], vec![true, true, true]],
+				vec![vec![false, true, false], vec![false, true, true], vec![false, true, false]]
+			],
+			BlockType::Z => [
+				vec![vec![false, false, false], vec![true, true, false], vec![false, true, true]],
+				vec![vec![false, false, true], vec![false, true, true], vec![false, true, false]],
+				vec![vec![false, false, false], vec![true, true, false], vec![false, true, true]],
+				vec![vec![false, false, true], vec![false, true, true], vec![false, true, false]]
+			]
+		}
+	}
+
+	fn kicks(&self, board : &Board, block : &Block, _clockwise : bool) -> &'static [(isize, isize)] {
+		match block.kind() {
+			BlockType::I | BlockType::O => NO_KICKS,
+			BlockType::J | BlockType::L | BlockType::T if center_column_blocked(board, block) => NO_KICKS,
+			_ => &[(0, 0), (1, 0), (-1, 0)]
+		}
+	}
+}
+
+/// The center column rule of ARS: a J, L or T does not kick when the first of its cells that
+/// is blocked, going through the rows from the top and each row from the left, is in the
+/// middle column.
+fn center_column_blocked(board : &Board, block : &Block) -> bool {
+	let (x, y) = block.position();
+	let blocked = block.tiles().iter().enumerate().flat_map(|(dy, row)|
+		row.iter().enumerate().filter(|(_, tile)| tile.is_some()).map(move |(dx, _)| (dx, dy))
+	).find(|&(dx, dy)| board.is_occupied(x + dx as isize, y + dy as isize));
+	blocked.is_some_and(|(dx, _)| dx == 1)
+}
+
+struct Nrs;
+
+impl Rules for Nrs {
+	fn states(&self, name : BlockType) -> [Vec<Vec<bool>>; 4] {
+		match name {
+			BlockType::I => [
+				vec![vec![false, false, false, false], vec![false, false, false, false],
+					vec![true, true, true, true], vec![false, false, false, false]],
+				vec![vec![false, false, true, false], vec![false, false, true, false],
+					vec![false, false, true, false], vec![false, false, true, false]],
+				vec![vec![false, false, false, false], vec![false, false, false, false],
+					vec![true, true, true, true], vec![false, false, false, false]],
+				vec![vec![false, false, true, false], vec![false, false, true, false],
+					vec![false, false, true, false], vec![false, false, true, false]]
+			],
+			BlockType::J => [
+				vec![vec![false, false, false], vec![true, true, true], vec![false, false, true]],
+				vec![vec![false, true, false], vec![false, true, false], vec![true, true, false]],
+				vec![vec![true, false, false], vec![true, true, true], vec![false, false, false]],
+				vec![vec![false, true, true], vec![false, true, false], vec![false, true, false]]
+			],
+			BlockType::L => [
+				vec![vec![false, false, false], vec![true, true, true], vec![true, false, false]],
+				vec![vec![true, true, false], vec![false, true, false], vec![false, true, false]],
+				vec![vec![false, false, true], vec![true, true, true], vec![false, false, false]],
+				vec![vec![false, true, false], vec![false, true, false], vec![false, true, true]]
+			],
+			BlockType::O => o_states(),
+			BlockType::S => [
+				vec![vec![false, false, false], vec![false, true, true], vec![true, true, false]],
+				vec![vec![false, true, false], vec![false, true, true], vec![false, false, true]],
+				vec![vec![false, false, false], vec![false, true, true], vec![true, true, false]],
+				vec![vec![false, true, false], vec![false, true, true], vec![false, false, true]]
+			],
+			BlockType::T => [
+				vec![vec![false, false, false], vec![true, true, true], vec![false, true, false]],
+				vec![vec![false, true, false], vec![true, true, false], vec![false, true, false]],
+				vec![vec![false, true, false], vec![true, true, true], vec![false, false, false]],
+				vec![vec![false, true, false], vec![false, true, true], vec![false, true, false]]
+			],
+			BlockType::Z => [
+				vec![vec![false, false, false], vec![true, true, false], vec![false, true, true]],
+				vec![vec![false, false, true], vec![false, true, true], vec![false, true, false]],
+				vec![vec![false, false, false], vec![true, true, false], vec![false, true, true]],
+				vec![vec![false, false, true], vec![false, true, true], vec![false, true, false]]
+			]
+		}
+	}
+
+	fn kicks(&self, _board : &Board, _block : &Block, _clockwise : bool) -> &'static [(isize, isize)] {
+		NO_KICKS
+	}
+}
+
+struct Sega;
+
+impl Rules for Sega {
+	fn states(&self, name : BlockType) -> [Vec<Vec<bool>>; 4] {
+		Ars.states(name)
+	}
+
+	fn kicks(&self, _board : &Board, _block : &Block, _clockwise : bool) -> &'static [(isize, isize)] {
+		NO_KICKS
+	}
+}
+
+fn o_states() -> [Vec<Vec<bool>>; 4] {
+	[
+		vec![vec![true, true], vec![true, true]],
+		vec![vec![true, true], vec![true, true]],
+		vec![vec![true, true], vec![true, true]],
+		vec![vec![true, true], vec![true, true]]
+	]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::block::{create_block, rotate};
+	use crate::board::Cell;
+
+	fn place(name : BlockType, system : RotationSystem, x : isize, y : isize) -> Block {
+		let mut block = create_block(name, system);
+		block.x_pos = x;
+		block.y_pos = y;
+		block
+	}
+
+	#[test]
+	fn ars_kicks_one_cell_off_the_wall() {
+		let board = Board::new(10, 20, 0);
+		for name in [BlockType::T, BlockType::L] {
+			// Upright with the empty left column outside the wall.
+			let mut block = place(name, RotationSystem::Ars, -1, 5);
+			block.rotation = 3;
+			assert_eq!(rotate(&board, &mut block, true), Some(1));
+			assert_eq!(block.rotation(), 0);
+			assert_eq!(block.position(), (0, 5));
+		}
+	}
+
+	#[test]
+	fn ars_does_not_kick_when_the_center_column_is_blocked() {
+		let mut board = Board::new(10, 20, 0);
+		// The first blocked cell of the turned L is in its left column, so it kicks.
+		board[5][3] = Some(Cell::Garbage);
+		let mut block = place(BlockType::L, RotationSystem::Ars, 3, 5);
+		assert_eq!(rotate(&board, &mut block, true), Some(1));
+		assert_eq!(block.position(), (4, 5));
+
+		// Here it is in the middle column, so the L stays as it is even though a kick would fit.
+		board[5][3] = None;
+		board[5][4] = Some(Cell::Garbage);
+		let mut block = place(BlockType::L, RotationSystem::Ars, 3, 5);
+		assert_eq!(rotate(&board, &mut block, true), None);
+		assert_eq!(block.rotation(), 0);
+		assert_eq!(block.position(), (3, 5));
+	}
+
+	#[test]
+	fn nrs_i_s_and_z_toggle_between_two_states() {
+		let board = Board::new(10, 20, 0);
+		for name in [BlockType::I, BlockType::S, BlockType::Z] {
+			let mut block = place(name, RotationSystem::Nrs, 3, 5);
+			let flat = block.tiles().to_vec();
+			assert_eq!(rotate(&board, &mut block, true), Some(0));
+			let upright = block.tiles().to_vec();
+			assert_ne!(upright, flat);
+			assert_eq!(rotate(&board, &mut block, true), Some(0));
+			assert_eq!(block.tiles(), flat);
+			assert_eq!(rotate(&board, &mut block, false), Some(0));
+			assert_eq!(block.tiles(), upright);
+			assert_eq!(block.position(), (3, 5));
+		}
+	}
+
+	#[test]
+	fn nrs_does_not_kick() {
+		let board = Board::new(10, 20, 0);
+		// An upright I against the left wall has no room to lie flat.
+		let mut block = place(BlockType::I, RotationSystem::Nrs, -2, 5);
+		block.rotation = 1;
+		assert_eq!(rotate(&board, &mut block, true), None);
+		assert_eq!(block.rotation(), 1);
+		assert_eq!(block.position(), (-2, 5));
+	}
+}